@@ -0,0 +1,666 @@
+//! ABC notation reader. Supports the parts of ABC 2.1 that describe a single
+//! melody line: header fields, key signatures with modes, accidentals that
+//! carry through a bar, note lengths, broken rhythm, tuplets, chords, ties
+//! and rests. Repeat signs are read as plain bar lines and decorations,
+//! grace notes, chord symbols and lyrics are skipped.
+
+use std::collections::HashMap;
+
+use level::{Melody, Note};
+use waves::notes;
+
+const LETTERS: &str = "CDEFGAB";
+const LETTER_PITCH: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+/// Position of each letter on the circle of fifths, relative to C.
+const LETTER_FIFTHS: [i32; 7] = [0, 2, 4, -1, 1, 3, 5];
+const SHARP_ORDER: &str = "FCGDAEB";
+const DEFAULT_TEMPO: f32 = 120.0;
+
+fn letter_index(c: char) -> Option<usize> {
+    LETTERS.find(c.to_ascii_uppercase())
+}
+
+/// Parses a fraction like `1/8`, `3/4` or `2`.
+fn parse_fraction(s: &str) -> Option<f32> {
+    let mut parts = s.trim().splitn(2, '/');
+    let num: f32 = parts.next()?.trim().parse().ok()?;
+    let den: f32 = match parts.next() {
+        Some(d) => d.trim().parse().ok()?,
+        None => 1.0,
+    };
+    if den > 0.0 {
+        Some(num / den)
+    } else {
+        None
+    }
+}
+
+/// Length of a bar in whole notes.
+fn parse_meter(s: &str) -> Option<f32> {
+    match s.trim() {
+        "C" => Some(1.0),
+        "C|" => Some(1.0),
+        "none" | "" => None,
+        m => {
+            let mut parts = m.splitn(2, '/');
+            let num: f32 = parts
+                .next()?
+                .split('+')
+                .map(|n| n.trim().parse::<f32>().ok())
+                .sum::<Option<f32>>()?;
+            let den: f32 = parts.next()?.trim().parse().ok()?;
+            Some(num / den)
+        }
+    }
+}
+
+/// Accidental for each letter, C to B, in the given key such as `G`,
+/// `Bb`, `F#m`, `Ddor` or `none`.
+fn parse_key(s: &str) -> Result<[i32; 7], String> {
+    let mut key = [0; 7];
+    let s = s.trim();
+    let mut chars = s.chars();
+    let tonic = match chars.next() {
+        Some(c) if letter_index(c).is_some() => c,
+        _ if s.is_empty() || s.starts_with("none") || s.starts_with("HP") => return Ok(key),
+        _ => return Err(format!("'{}' is not a key", s)),
+    };
+    let rest: String = chars.collect();
+    let (accidental, mode) = if let Some(mode) = rest.strip_prefix('#') {
+        (7, mode)
+    } else if let Some(mode) = rest.strip_prefix('b') {
+        (-7, mode)
+    } else {
+        (0, &rest[..])
+    };
+    let mode = mode
+        .split_whitespace()
+        .next()
+        .filter(|m| !m.contains('='))
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    let prefix: String = mode.chars().take(3).collect();
+    let mode_fifths = match prefix.as_str() {
+        "" | "maj" | "ion" => 0,
+        "m" | "mi" | "min" | "aeo" => -3,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "lyd" => 1,
+        "loc" => -5,
+        _ => return Err(format!("'{}' is not a known mode", mode)),
+    };
+
+    let fifths = LETTER_FIFTHS[letter_index(tonic).unwrap()] + accidental + mode_fifths;
+    if fifths.abs() > 7 {
+        return Err(format!("key '{}' needs more than seven accidentals", s));
+    }
+    let order: Vec<char> = if fifths > 0 {
+        SHARP_ORDER.chars().collect()
+    } else {
+        SHARP_ORDER.chars().rev().collect()
+    };
+    for c in order.iter().take(fifths.unsigned_abs() as usize) {
+        key[letter_index(*c).unwrap()] = fifths.signum();
+    }
+    Ok(key)
+}
+
+/// Reading state for the body of one tune.
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    key: [i32; 7],
+    bar_accidentals: HashMap<(usize, i32), i32>,
+    unit: f32,
+    meter: Option<f32>,
+    tempo: Option<f32>,
+    time: f32,
+    notes: Vec<Note>,
+    /// Notes followed by `-`, waiting for the next note of the same pitch.
+    ties: Vec<(i32, usize)>,
+    tuplet: Option<(usize, f32)>,
+    broken: f32,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        if self.pos > start {
+            self.chars[start..self.pos]
+                .iter()
+                .collect::<String>()
+                .parse()
+                .ok()
+        } else {
+            None
+        }
+    }
+
+    fn skip_until(&mut self, end: char) {
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            if c == end {
+                break;
+            }
+        }
+    }
+
+    /// Length multiplier such as `3`, `/`, `3/2` or `//`.
+    fn length(&mut self) -> f32 {
+        let num = self.number().unwrap_or(1) as f32;
+        let mut den = 1.0;
+        while self.eat('/') {
+            match self.number() {
+                Some(d) if d > 0 => den *= d as f32,
+                _ => den *= 2.0,
+            }
+        }
+        num / den
+    }
+
+    fn field(&mut self, name: char, value: &str) -> Result<(), String> {
+        match name {
+            'K' => {
+                self.key = parse_key(value)?;
+                self.bar_accidentals.clear();
+            }
+            'L' => {
+                self.unit = parse_fraction(value)
+                    .filter(|&u| u > 0.0)
+                    .ok_or(format!("'{}' is not a note length", value))?
+            }
+            'M' => self.meter = parse_meter(value),
+            'Q' => self.tempo = parse_tempo(value, self.unit),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Pitch of a note, with accidentals from the note, the bar and the
+    /// key signature applied in that order.
+    fn pitch(&mut self) -> Option<i32> {
+        let mut accidental = None;
+        loop {
+            match self.peek() {
+                Some('^') => accidental = Some(accidental.unwrap_or(0) + 1),
+                Some('_') => accidental = Some(accidental.unwrap_or(0) - 1),
+                Some('=') => accidental = Some(0),
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        let c = self.peek()?;
+        let letter = letter_index(c)?;
+        self.pos += 1;
+        let mut octave = if c.is_ascii_uppercase() { 4 } else { 5 };
+        loop {
+            match self.peek() {
+                Some('\'') => octave += 1,
+                Some(',') => octave -= 1,
+                _ => break,
+            }
+            self.pos += 1;
+        }
+
+        let accidental = match accidental {
+            Some(a) => {
+                self.bar_accidentals.insert((letter, octave), a);
+                a
+            }
+            None => *self
+                .bar_accidentals
+                .get(&(letter, octave))
+                .unwrap_or(&self.key[letter]),
+        };
+        Some((octave + 1) * 12 + LETTER_PITCH[letter] + accidental)
+    }
+
+    /// Applies broken rhythm and tuplets to a note or rest of `units`
+    /// unit lengths and returns its length in beats.
+    fn beats(&mut self, units: f32) -> f32 {
+        let mut units = units * self.broken;
+        self.broken = 1.0;
+
+        let mut dots = 0;
+        let mut shorter = false;
+        while let Some(c) = self.peek() {
+            if c == '>' || c == '<' {
+                shorter = c == '<';
+                dots += 1;
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if dots > 0 {
+            let cut = 0.5f32.powi(dots);
+            if shorter {
+                units *= cut;
+                self.broken = 2.0 - cut;
+            } else {
+                units *= 2.0 - cut;
+                self.broken = cut;
+            }
+        }
+
+        if let Some((remaining, factor)) = self.tuplet {
+            units *= factor;
+            self.tuplet = if remaining > 1 {
+                Some((remaining - 1, factor))
+            } else {
+                None
+            };
+        }
+
+        units * self.unit * 4.0
+    }
+
+    /// Adds the notes sounding together at the current time, extending any
+    /// tied notes of the same pitch instead of restarting them.
+    fn push(&mut self, pitches: Vec<(i32, bool)>, beats: f32) {
+        let ties: Vec<(i32, usize)> = self.ties.drain(..).collect();
+        for (midi, tied) in pitches {
+            let existing = ties.iter().find(|&&(m, _)| m == midi).map(|&(_, i)| i);
+            let idx = match existing {
+                Some(i) => {
+                    self.notes[i].beats += beats;
+                    i
+                }
+                None => {
+                    self.notes.push(Note {
+                        freq: notes::midi_to_freq(midi),
+                        start: self.time,
+                        beats,
+                        pattern: None,
+                    });
+                    self.notes.len() - 1
+                }
+            };
+            if tied {
+                self.ties.push((midi, idx));
+            }
+        }
+        self.time += beats;
+    }
+
+    fn body_line(&mut self) -> Result<(), String> {
+        while let Some(c) = self.peek() {
+            match c {
+                '%' => break,
+                '"' => {
+                    self.pos += 1;
+                    self.skip_until('"');
+                }
+                '!' | '+' => {
+                    self.pos += 1;
+                    self.skip_until(c);
+                }
+                '{' => self.skip_until('}'),
+                '|' | ':' => {
+                    self.pos += 1;
+                    self.bar_accidentals.clear();
+                    self.number();
+                }
+                '[' if self.peek_at(2) == Some(':') => {
+                    self.pos += 1;
+                    let start = self.pos;
+                    self.skip_until(']');
+                    if self.chars[self.pos - 1] != ']' || self.pos - start < 3 {
+                        return Err(format!("unclosed inline field at column {}", start));
+                    }
+                    let field = &self.chars[start..self.pos - 1];
+                    let value: String = field[2..].iter().collect();
+                    self.field(field[0], &value)?;
+                }
+                '[' if self.peek_at(1).is_some_and(|c| c == '|' || c.is_ascii_digit()) => {
+                    self.pos += 1;
+                    self.number();
+                }
+                '[' => {
+                    self.pos += 1;
+                    let mut pitches = Vec::new();
+                    let mut first_length = None;
+                    while self.peek().is_some_and(|c| c != ']') {
+                        match self.pitch() {
+                            Some(midi) => {
+                                let length = self.length();
+                                first_length.get_or_insert(length);
+                                pitches.push((midi, self.eat('-')));
+                            }
+                            None => self.pos += 1,
+                        }
+                    }
+                    self.eat(']');
+                    let units = first_length.unwrap_or(1.0) * self.length();
+                    let beats = self.beats(units);
+                    let tie_all = self.eat('-');
+                    let pitches = pitches.into_iter().map(|(m, t)| (m, t || tie_all)).collect();
+                    self.push(pitches, beats);
+                }
+                '(' if self.peek_at(1).is_some_and(|c| c.is_ascii_digit()) => {
+                    self.pos += 1;
+                    let p = self.number().unwrap_or(3);
+                    let mut q = match p {
+                        2 | 4 | 8 => 3,
+                        _ => 2,
+                    };
+                    let mut r = p;
+                    if self.eat(':') {
+                        q = self.number().unwrap_or(q);
+                        if self.eat(':') {
+                            r = self.number().unwrap_or(p);
+                        }
+                    }
+                    if p == 0 || q == 0 {
+                        return Err(format!("tuplet ({}:{} at column {}", p, q, self.pos));
+                    }
+                    self.tuplet = Some((r as usize, q as f32 / p as f32));
+                }
+                'z' | 'x' => {
+                    self.pos += 1;
+                    let units = self.length();
+                    self.ties.clear();
+                    self.time += self.beats(units);
+                }
+                'Z' | 'X' => {
+                    self.pos += 1;
+                    let bars = self.number().unwrap_or(1) as f32;
+                    let bar = self.meter.ok_or("multi-bar rest needs a meter (M:)")?;
+                    self.ties.clear();
+                    self.time += bars * bar * 4.0;
+                }
+                '^' | '_' | '=' | 'A'..='G' | 'a'..='g' => {
+                    let midi = self.pitch().ok_or(format!(
+                        "accidental without a note at column {}",
+                        self.pos + 1
+                    ))?;
+                    let units = self.length();
+                    let beats = self.beats(units);
+                    let tied = self.eat('-');
+                    self.push(vec![(midi, tied)], beats);
+                }
+                _ => self.pos += 1,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Tempo from a `Q:` field such as `1/4=120`, `"Allegro" 3/8=80` or the
+/// legacy `120`, in quarter-note beats per minute.
+fn parse_tempo(value: &str, unit: f32) -> Option<f32> {
+    let value = match value.rfind('"') {
+        Some(i) => &value[i + 1..],
+        None => value,
+    };
+    let mut parts = value.splitn(2, '=');
+    let first = parts.next()?.trim();
+    match parts.next() {
+        Some(bpm) => {
+            let beat: f32 = first
+                .split_whitespace()
+                .map(parse_fraction)
+                .sum::<Option<f32>>()?;
+            let bpm: f32 = bpm.trim().parse().ok()?;
+            Some(bpm * beat * 4.0)
+        }
+        None => first.parse::<f32>().ok().map(|bpm| bpm * unit * 4.0),
+    }
+}
+
+/// Reads one tune from an ABC file. `tune` picks the tune by its `X:`
+/// reference number; by default the first tune is used.
+pub fn melody(source: &str, tune: Option<u32>) -> Result<Melody, String> {
+    let mut lines = source.lines().enumerate();
+
+    // Find the start of the requested tune.
+    let mut found = false;
+    for (_, line) in lines.by_ref() {
+        if let Some(number) = line.strip_prefix("X:") {
+            let number = number.trim().parse::<u32>().ok();
+            if tune.is_none() || tune == number {
+                found = true;
+                break;
+            }
+        }
+    }
+    if !found {
+        return Err(match tune {
+            Some(n) => format!("no tune with X:{}", n),
+            None => "no tune found (tunes start with an X: line)".to_owned(),
+        });
+    }
+
+    let mut parser = Parser {
+        chars: Vec::new(),
+        pos: 0,
+        key: [0; 7],
+        bar_accidentals: HashMap::new(),
+        unit: 0.0,
+        meter: Some(1.0),
+        tempo: None,
+        time: 0.0,
+        notes: Vec::new(),
+        ties: Vec::new(),
+        tuplet: None,
+        broken: 1.0,
+    };
+    let mut title = None;
+    let mut in_body = false;
+    let mut pending_tempo = None;
+
+    for (number, line) in lines {
+        let line = line.trim_end();
+        if line.trim().is_empty() || line.starts_with("X:") {
+            break;
+        }
+        // Comments and `%%` directives, in the header or the body.
+        if line.starts_with('%') {
+            continue;
+        }
+        let is_field = {
+            let mut chars = line.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), Some(':')) => c.is_ascii_alphabetic(),
+                _ => false,
+            }
+        };
+
+        let result = if is_field {
+            let name = line.chars().next().unwrap();
+            let value = line[2..].split('%').next().unwrap_or("").trim();
+            match name {
+                'T' if title.is_none() => {
+                    title = Some(value.to_owned());
+                    Ok(())
+                }
+                // The default unit depends on the meter, so tempo waits for
+                // the body when it is given in units.
+                'Q' if !in_body => {
+                    pending_tempo = Some(value.to_owned());
+                    Ok(())
+                }
+                'K' if !in_body => {
+                    in_body = true;
+                    if parser.unit == 0.0 {
+                        parser.unit = match parser.meter {
+                            Some(m) if m < 0.75 => 1.0 / 16.0,
+                            _ => 1.0 / 8.0,
+                        };
+                    }
+                    if let Some(ref q) = pending_tempo {
+                        parser.tempo = parse_tempo(q, parser.unit);
+                    }
+                    parser.field(name, value)
+                }
+                _ => parser.field(name, value),
+            }
+        } else if in_body {
+            parser.chars = line.chars().collect();
+            parser.pos = 0;
+            parser.body_line()
+        } else {
+            Err("music before the K: field".to_owned())
+        };
+        result.map_err(|e| format!("line {}: {}", number + 1, e))?;
+    }
+
+    if parser.notes.is_empty() {
+        return Err("tune contains no notes".to_owned());
+    }
+    Ok(Melody {
+        title,
+        tempo: Some(parser.tempo.unwrap_or(DEFAULT_TEMPO)),
+        notes: parser.notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn midi(melody: &Melody) -> Vec<i32> {
+        melody
+            .notes
+            .iter()
+            .map(|n| notes::freq_to_midi(n.freq).round() as i32)
+            .collect()
+    }
+
+    #[test]
+    fn unclosed_inline_field_is_an_error() {
+        for body in &["CDE [K:", "CDE [K:G", "CDE []:"] {
+            let source = format!("X:1\nK:C\n{}", body);
+            assert!(melody(&source, None).is_err(), "{}", body);
+        }
+    }
+
+    #[test]
+    fn comment_lines_are_skipped() {
+        let source = "X:1\n% comment\n%%MIDI program 1\nT:a\nK:C\n% body comment\nCDE % end";
+        let tune = melody(source, None).unwrap();
+        assert_eq!(tune.title, Some("a".to_owned()));
+        assert_eq!(midi(&tune), vec![60, 62, 64]);
+    }
+
+    fn lengths(melody: &Melody) -> Vec<(f32, f32)> {
+        melody.notes.iter().map(|n| (n.start, n.beats)).collect()
+    }
+
+    #[test]
+    fn inline_field_changes_key() {
+        let tune = melody("X:1\nK:C\nF [K:G] F", None).unwrap();
+        assert_eq!(midi(&tune), vec![65, 66]);
+    }
+
+    #[test]
+    fn key_signatures() {
+        assert_eq!(parse_key("C"), Ok([0; 7]));
+        assert_eq!(parse_key("G"), Ok([0, 0, 0, 1, 0, 0, 0]));
+        assert_eq!(parse_key("Bb"), Ok([0, 0, -1, 0, 0, 0, -1]));
+        assert_eq!(parse_key("F#m"), Ok([1, 0, 0, 1, 1, 0, 0]));
+        assert_eq!(parse_key("Am"), Ok([0; 7]));
+        assert_eq!(parse_key("Ddor"), Ok([0; 7]));
+        assert_eq!(parse_key("none"), Ok([0; 7]));
+        assert!(parse_key("H").is_err());
+        assert!(parse_key("Cfoo").is_err());
+        // F flat major would need eight flats.
+        assert!(parse_key("Fb").is_err());
+    }
+
+    #[test]
+    fn non_ascii_mode_is_an_error() {
+        for key in &["Ca€", "Cxyé", "Cé", "Dm€"] {
+            assert!(parse_key(key).is_err(), "{}", key);
+        }
+    }
+
+    #[test]
+    fn accidentals_last_to_the_bar_line() {
+        let tune = melody("X:1\nK:D\nFcF=F F|F", None).unwrap();
+        assert_eq!(midi(&tune), vec![66, 73, 66, 65, 65, 66]);
+    }
+
+    #[test]
+    fn broken_rhythm() {
+        let tune = melody("X:1\nL:1/4\nK:C\nC>D E<F G>>A", None).unwrap();
+        assert_eq!(
+            lengths(&tune),
+            vec![
+                (0.0, 1.5),
+                (1.5, 0.5),
+                (2.0, 0.5),
+                (2.5, 1.5),
+                (4.0, 1.75),
+                (5.75, 0.25),
+            ]
+        );
+    }
+
+    #[test]
+    fn ties_join_notes_of_the_same_pitch() {
+        let tune = melody("X:1\nL:1/4\nK:C\nC-C D-|D [CE]-[CE] E-F", None).unwrap();
+        assert_eq!(midi(&tune), vec![60, 62, 60, 64, 64, 65]);
+        assert_eq!(
+            lengths(&tune),
+            vec![
+                (0.0, 2.0),
+                (2.0, 2.0),
+                (4.0, 2.0),
+                (4.0, 2.0),
+                (6.0, 1.0),
+                (7.0, 1.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn tuplets() {
+        let tune = melody("X:1\nK:C\n(3CDE F (2GA B", None).unwrap();
+        let expected = [
+            (0.0, 1.0 / 3.0),
+            (1.0 / 3.0, 1.0 / 3.0),
+            (2.0 / 3.0, 1.0 / 3.0),
+            (1.0, 0.5),
+            (1.5, 0.75),
+            (2.25, 0.75),
+            (3.0, 0.5),
+        ];
+        let got = lengths(&tune);
+        assert_eq!(got.len(), expected.len());
+        for (&(start, beats), &(want_start, want_beats)) in got.iter().zip(expected.iter()) {
+            assert!((start - want_start).abs() < 1e-4, "{:?}", got);
+            assert!((beats - want_beats).abs() < 1e-4, "{:?}", got);
+        }
+    }
+
+    #[test]
+    fn empty_tuplets_are_an_error() {
+        for body in &["(3:0 CDE", "(0 CDE", "(0:2 CD"] {
+            let source = format!("X:1\nK:C\n{}", body);
+            assert!(melody(&source, None).is_err(), "{}", body);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+//! Difficulty that follows the player. While a level is played, enemy
+//! speed and the cue's detune are tightened when the player shoots more of
+//! their notes than the difficulty profile's target, and eased when they
+//! shoot fewer.
+
+use std::collections::VecDeque;
+
+/// Outcomes the success rate is taken over, most recent last.
+const WINDOW: usize = 8;
+/// Outcomes needed before anything changes.
+const MIN_OUTCOMES: usize = 4;
+/// How far each outcome moves the adjustment, per unit of difference from
+/// the target.
+const STEP: f32 = 0.25;
+/// Enemy speed changes by up to this fraction either way.
+const SPEED_SWING: f32 = 0.5;
+
+pub struct Adaptive {
+    /// Share of notes the player should shoot, from 0 to 1.
+    target: f32,
+    recent: VecDeque<bool>,
+    /// From -1 (easiest) to 1 (hardest); 0 plays the level as set.
+    adjustment: f32,
+}
+
+impl Adaptive {
+    pub fn new(target: f32) -> Adaptive {
+        Adaptive {
+            target,
+            recent: VecDeque::new(),
+            adjustment: 0.0,
+        }
+    }
+
+    /// Takes whether the player shot a note they were meant to.
+    pub fn record(&mut self, hit: bool) {
+        self.recent.push_back(hit);
+        if self.recent.len() > WINDOW {
+            self.recent.pop_front();
+        }
+        if self.recent.len() < MIN_OUTCOMES {
+            return;
+        }
+        let hits = self.recent.iter().filter(|&&h| h).count();
+        let rate = hits as f32 / self.recent.len() as f32;
+        self.adjustment = (self.adjustment + STEP * (rate - self.target)).clamp(-1.0, 1.0);
+    }
+
+    /// Multiplies enemy speeds.
+    pub fn speed(&self) -> f32 {
+        1.0 + SPEED_SWING * self.adjustment
+    }
+
+    /// Multiplies the cue's detune: twice as wide at the easiest, so that
+    /// it beats faster and stands out, and half as wide at the hardest.
+    pub fn cue_detune(&self) -> f32 {
+        2.0f32.powf(-self.adjustment)
+    }
+}
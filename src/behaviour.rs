@@ -0,0 +1,173 @@
+//! How enemies move. Every enemy still flies with its velocity and bounces
+//! off the walls; a pattern steers that velocity, or places the enemy
+//! directly, before each step.
+
+use ggez::graphics::{Point2, Vector2};
+
+use rand::prelude::*;
+
+use {SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Height of the band a drifting enemy bobs through.
+const DRIFT_AMPLITUDE: f32 = 60.0;
+/// Radians per second.
+const DRIFT_RATE: f32 = 2.0;
+/// How quickly a homing enemy turns towards the player, per second.
+const HOMING_TURN: f32 = 1.5;
+/// Homing enemies hover this far above the player rather than landing on
+/// them.
+const HOMING_HOVER: f32 = 200.0;
+const TELEPORT_TIME: f32 = 3.0;
+const PAUSE_MOVE_TIME: f32 = 2.0;
+const PAUSE_STOP_TIME: f32 = 1.0;
+const FORMATION_COLUMNS: usize = 6;
+const FORMATION_TOP: f32 = 60.0;
+/// How far the whole formation sways from side to side.
+const FORMATION_SWAY: f32 = 150.0;
+const FORMATION_RATE: f32 = 0.5;
+const DESCEND_STEP: f32 = 40.0;
+/// Space kept clear at the bottom of the screen, matching the walls'
+/// gutter.
+const GUTTER: f32 = 50.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// Flies in a straight line, bouncing off the walls.
+    #[default]
+    Bounce,
+    /// Bounces from side to side while bobbing up and down.
+    Drift,
+    /// Steers towards the player.
+    Homing,
+    /// Vanishes and reappears somewhere else every few seconds.
+    Teleport,
+    /// Stops still for a moment every few seconds.
+    Pause,
+    /// Holds a place in a block of enemies that sways as one.
+    Formation,
+    /// Marches sideways and steps down at each wall, like Space Invaders.
+    Descend,
+}
+
+impl Pattern {
+    pub fn parse(name: &str) -> Option<Pattern> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "bounce" => Some(Pattern::Bounce),
+            "drift" => Some(Pattern::Drift),
+            "homing" => Some(Pattern::Homing),
+            "teleport" => Some(Pattern::Teleport),
+            "pause" => Some(Pattern::Pause),
+            "formation" => Some(Pattern::Formation),
+            "descend" => Some(Pattern::Descend),
+            _ => None,
+        }
+    }
+}
+
+/// A pattern plus the state it needs between frames.
+#[derive(Debug, Clone)]
+pub struct Behaviour {
+    pub pattern: Pattern,
+    /// Place in the formation, for `Formation`.
+    pub slot: usize,
+    /// Seconds since the enemy appeared.
+    clock: f32,
+    /// The velocity the enemy was given, which patterns return to.
+    cruise: Vector2,
+    /// Height the enemy started at, which drifting bobs around.
+    base_y: f32,
+    /// A descending enemy has reached the ground.
+    landed: bool,
+}
+
+impl Behaviour {
+    pub fn new(pattern: Pattern, pos: Point2, velocity: Vector2) -> Behaviour {
+        Behaviour {
+            pattern,
+            slot: 0,
+            clock: 0.0,
+            cruise: velocity,
+            base_y: pos.y.max(DRIFT_AMPLITUDE),
+            landed: false,
+        }
+    }
+
+    pub fn landed(&self) -> bool {
+        self.landed
+    }
+
+    /// Steers an enemy of the given size for one step. `player` is the
+    /// player's centre and `time` the seconds since the level started.
+    pub fn update(
+        &mut self,
+        pos: &mut Point2,
+        velocity: &mut Vector2,
+        size: f32,
+        player: Point2,
+        time: f32,
+        seconds: f32,
+    ) {
+        let last_clock = self.clock;
+        self.clock += seconds;
+
+        match self.pattern {
+            Pattern::Bounce => (),
+            Pattern::Drift => {
+                velocity.y = 0.0;
+                pos.y = self.base_y + DRIFT_AMPLITUDE * (self.clock * DRIFT_RATE).sin();
+            }
+            Pattern::Homing => {
+                let goal = Point2::new(player.x - size / 2.0, player.y - HOMING_HOVER);
+                let offset = goal - *pos;
+                if offset.norm() > 1.0 {
+                    let wanted = offset.normalize() * self.cruise.norm();
+                    *velocity = *velocity + (wanted - *velocity) * (HOMING_TURN * seconds).min(1.0);
+                }
+            }
+            Pattern::Teleport => {
+                if (self.clock / TELEPORT_TIME).floor() > (last_clock / TELEPORT_TIME).floor() {
+                    pos.x = random::<f32>() * (SCREEN_WIDTH as f32 - size);
+                    pos.y = random::<f32>() * (SCREEN_HEIGHT as f32 - GUTTER - size);
+                }
+            }
+            Pattern::Pause => {
+                let moving = self.clock % (PAUSE_MOVE_TIME + PAUSE_STOP_TIME) < PAUSE_MOVE_TIME;
+                if !moving {
+                    *velocity = Vector2::new(0.0, 0.0);
+                } else if velocity.norm() == 0.0 {
+                    *velocity = self.cruise;
+                } else {
+                    // Keep the direction walls have bounced it into.
+                    self.cruise = *velocity;
+                }
+            }
+            Pattern::Formation => {
+                let column = (self.slot % FORMATION_COLUMNS) as f32;
+                let row = (self.slot / FORMATION_COLUMNS) as f32;
+                let spacing = size * 1.5;
+                let width = spacing * (FORMATION_COLUMNS as f32 - 1.0) + size;
+                let left = (SCREEN_WIDTH as f32 - width) / 2.0
+                    + FORMATION_SWAY * (time * FORMATION_RATE).sin();
+                *velocity = Vector2::new(0.0, 0.0);
+                pos.x = left + column * spacing;
+                pos.y = FORMATION_TOP + row * spacing;
+            }
+            Pattern::Descend => {
+                velocity.y = 0.0;
+                if velocity.x == 0.0 {
+                    velocity.x = self.cruise.norm().max(1.0);
+                }
+                // About to hit a wall: step down, landing once at the
+                // bottom.
+                let next_x = pos.x + velocity.x * seconds;
+                if next_x < 0.0 || next_x + size > SCREEN_WIDTH as f32 {
+                    pos.y += DESCEND_STEP;
+                    if pos.y + size > SCREEN_HEIGHT as f32 - GUTTER {
+                        self.landed = true;
+                    }
+                }
+            }
+        }
+    }
+}
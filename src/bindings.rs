@@ -0,0 +1,175 @@
+//! Which key does what during play. Bindings are saved to `bindings.toml`
+//! in the user config directory, e.g.
+//!
+//! ```toml
+//! left = "A"
+//! right = "D"
+//! shoot = "Space"
+//! ```
+//!
+//! Actions left out keep their default key. Key names are SDL's, as shown
+//! on the controls screen.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use ggez::event::Keycode;
+use ggez::{Context, GameError, GameResult};
+
+use toml;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Left,
+    Right,
+    Shoot,
+    Jump,
+    PitchUp,
+    PitchDown,
+    FineUp,
+    FineDown,
+    /// Reads the HUD aloud.
+    Hud,
+    Pause,
+    Screenshot,
+}
+
+pub const ACTIONS: [Action; 11] = [
+    Action::Left,
+    Action::Right,
+    Action::Shoot,
+    Action::Jump,
+    Action::PitchUp,
+    Action::PitchDown,
+    Action::FineUp,
+    Action::FineDown,
+    Action::Hud,
+    Action::Pause,
+    Action::Screenshot,
+];
+
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Action::Left => "Move left",
+            Action::Right => "Move right",
+            Action::Shoot => "Shoot",
+            Action::Jump => "Jump",
+            Action::PitchUp => "Pitch up a semitone",
+            Action::PitchDown => "Pitch down a semitone",
+            Action::FineUp => "Pitch up a little",
+            Action::FineDown => "Pitch down a little",
+            Action::Hud => "Read out the HUD",
+            Action::Pause => "Pause",
+            Action::Screenshot => "Screenshot",
+        }
+    }
+
+    /// The action's name in the bindings file.
+    fn config_name(&self) -> &'static str {
+        match *self {
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::Shoot => "shoot",
+            Action::Jump => "jump",
+            Action::PitchUp => "pitch_up",
+            Action::PitchDown => "pitch_down",
+            Action::FineUp => "fine_up",
+            Action::FineDown => "fine_down",
+            Action::Hud => "hud",
+            Action::Pause => "pause",
+            Action::Screenshot => "screenshot",
+        }
+    }
+
+    fn default_key(&self) -> Keycode {
+        match *self {
+            Action::Left => Keycode::Left,
+            Action::Right => Keycode::Right,
+            Action::Shoot => Keycode::Space,
+            Action::Jump => Keycode::Up,
+            Action::PitchUp => Keycode::W,
+            Action::PitchDown => Keycode::S,
+            Action::FineUp => Keycode::E,
+            Action::FineDown => Keycode::D,
+            Action::Hud => Keycode::H,
+            Action::Pause => Keycode::Escape,
+            Action::Screenshot => Keycode::P,
+        }
+    }
+}
+
+/// One key per action, in the order of `ACTIONS`.
+#[derive(Debug, Clone)]
+pub struct Bindings {
+    keys: Vec<Keycode>,
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        Bindings {
+            keys: ACTIONS.iter().map(|a| a.default_key()).collect(),
+        }
+    }
+}
+
+impl Bindings {
+    pub fn key(&self, action: Action) -> Keycode {
+        self.keys[index(action)]
+    }
+
+    pub fn action(&self, keycode: Keycode) -> Option<Action> {
+        self.keys
+            .iter()
+            .position(|&k| k == keycode)
+            .map(|i| ACTIONS[i])
+    }
+
+    /// Binds `keycode` to `action`. An action that already had the key
+    /// takes `action`'s old key instead, so no key does two things.
+    pub fn bind(&mut self, action: Action, keycode: Keycode) {
+        let old = self.key(action);
+        if let Some(other) = self.action(keycode) {
+            self.keys[index(other)] = old;
+        }
+        self.keys[index(action)] = keycode;
+    }
+
+    /// Loads bindings from the user config directory, falling back to the
+    /// defaults when there is no file.
+    pub fn load(ctx: &mut Context, path: &str) -> GameResult<Bindings> {
+        let mut bindings = Bindings::default();
+        if !ctx.filesystem.exists(path) {
+            return Ok(bindings);
+        }
+
+        let mut source = String::new();
+        ctx.filesystem.open(path)?.read_to_string(&mut source)?;
+        let error = |e: String| GameError::ResourceLoadError(format!("{}: {}", path, e));
+        let file: BTreeMap<String, String> =
+            toml::from_str(&source).map_err(|e| error(e.to_string()))?;
+        for (name, key) in file {
+            let action = ACTIONS
+                .iter()
+                .find(|a| a.config_name() == name)
+                .ok_or_else(|| error(format!("unknown action `{}`", name)))?;
+            let keycode =
+                Keycode::from_name(&key).ok_or_else(|| error(format!("unknown key `{}`", key)))?;
+            bindings.bind(*action, keycode);
+        }
+        Ok(bindings)
+    }
+
+    pub fn save(&self, ctx: &mut Context, path: &str) -> GameResult<()> {
+        let mut file = ctx.filesystem.create(path)?;
+        for &action in ACTIONS.iter() {
+            let key = toml::Value::String(self.key(action).name());
+            writeln!(file, "{} = {}", action.config_name(), key)?;
+        }
+        Ok(())
+    }
+}
+
+fn index(action: Action) -> usize {
+    ACTIONS.iter().position(|&a| a == action).unwrap()
+}
@@ -0,0 +1,115 @@
+use std::io::Read;
+
+use ggez::{Context, GameError, GameResult};
+
+use toml;
+
+use level::Level;
+use score::Score;
+
+/// How a finished level went.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LevelResult {
+    pub score: Score,
+    pub seconds: f32,
+}
+
+impl LevelResult {
+    pub fn accuracy(&self) -> f32 {
+        self.score.accuracy()
+    }
+
+    fn better_than(&self, other: &LevelResult) -> bool {
+        let (a, b) = (self.score.points, other.score.points);
+        a > b || (a == b && self.accuracy() > other.accuracy())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EntryFile {
+    file: String,
+    #[serde(default)]
+    unlocked: bool,
+    #[serde(default)]
+    min_accuracy: f32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CampaignFile {
+    level: Vec<EntryFile>,
+}
+
+pub struct CampaignEntry {
+    /// Path of the level file, which identifies the level in save data.
+    pub file: String,
+    pub level: Level,
+    /// Playable, either from the start or since the level before was
+    /// finished with enough accuracy.
+    pub unlocked: bool,
+    /// Accuracy needed on the previous level to unlock this one.
+    pub min_accuracy: f32,
+    pub best: Option<LevelResult>,
+}
+
+pub struct Campaign {
+    pub entries: Vec<CampaignEntry>,
+}
+
+impl Campaign {
+    /// Loads the campaign's level list and every level in it.
+    pub fn load(ctx: &mut Context, path: &str) -> GameResult<Campaign> {
+        let mut source = String::new();
+        ctx.filesystem.open(path)?.read_to_string(&mut source)?;
+        let file: CampaignFile = toml::from_str(&source)
+            .map_err(|e| GameError::ResourceLoadError(format!("{}: {}", path, e)))?;
+        if file.level.is_empty() {
+            return Err(GameError::ResourceLoadError(format!(
+                "{}: campaign has no levels",
+                path
+            )));
+        }
+
+        let mut entries = Vec::new();
+        for (i, entry) in file.level.into_iter().enumerate() {
+            if entry.min_accuracy < 0.0 || entry.min_accuracy > 1.0 {
+                return Err(GameError::ResourceLoadError(format!(
+                    "{}: level {} min_accuracy must be between 0 and 1",
+                    path,
+                    i + 1
+                )));
+            }
+            entries.push(CampaignEntry {
+                level: Level::load(ctx, &entry.file)?,
+                file: entry.file,
+                unlocked: entry.unlocked || i == 0,
+                min_accuracy: entry.min_accuracy,
+                best: None,
+            });
+        }
+        Ok(Campaign { entries })
+    }
+
+    pub fn is_unlocked(&self, idx: usize) -> bool {
+        self.entries[idx].unlocked
+    }
+
+    /// Records a finished level, keeping the best result, and unlocks the
+    /// next level if this run was accurate enough, whether or not it was
+    /// the best. Returns true if this was a new best.
+    pub fn record(&mut self, idx: usize, result: LevelResult) -> bool {
+        if let Some(next) = self.entries.get_mut(idx + 1) {
+            if result.accuracy() >= next.min_accuracy {
+                next.unlocked = true;
+            }
+        }
+
+        let entry = &mut self.entries[idx];
+        let improved = entry.best.as_ref().is_none_or(|b| result.better_than(b));
+        if improved {
+            entry.best = Some(result);
+        }
+        improved
+    }
+}
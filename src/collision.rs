@@ -0,0 +1,149 @@
+use ggez::graphics::{Point2, Rect, Vector2};
+
+/// Axis-aligned box, positioned by its top-left corner.
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Aabb {
+    pub fn new(pos: Point2, w: f32, h: f32) -> Aabb {
+        Aabb {
+            x: pos.x,
+            y: pos.y,
+            w,
+            h,
+        }
+    }
+
+    pub fn overlaps(&self, other: &Aabb) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+
+    pub fn center(&self) -> Point2 {
+        Point2::new(self.x + self.w / 2.0, self.y + self.h / 2.0)
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.w, self.h)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Circle {
+    pub center: Point2,
+    pub radius: f32,
+}
+
+impl Circle {
+    pub fn overlaps_aabb(&self, aabb: &Aabb) -> bool {
+        let nearest = Point2::new(
+            self.center.x.max(aabb.x).min(aabb.x + aabb.w),
+            self.center.y.max(aabb.y).min(aabb.y + aabb.h),
+        );
+        (self.center - nearest).norm_squared() < self.radius * self.radius
+    }
+}
+
+/// A half-line, such as the path of a shot.
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point2,
+    /// Unit length.
+    pub direction: Vector2,
+}
+
+impl Ray {
+    /// How far along the ray it enters the box, or 0 if it starts inside.
+    pub fn hits(&self, aabb: &Aabb) -> Option<f32> {
+        let slabs = [
+            (self.origin.x, self.direction.x, aabb.x, aabb.x + aabb.w),
+            (self.origin.y, self.direction.y, aabb.y, aabb.y + aabb.h),
+        ];
+        let (mut near, mut far) = (0.0f32, f32::INFINITY);
+        for &(origin, direction, low, high) in &slabs {
+            if direction == 0.0 {
+                // Parallel to this slab, so it has to start between its
+                // sides.
+                if origin <= low || origin >= high {
+                    return None;
+                }
+            } else {
+                let (a, b) = ((low - origin) / direction, (high - origin) / direction);
+                near = near.max(a.min(b));
+                far = far.min(a.max(b));
+            }
+        }
+        if near <= far {
+            Some(near)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square() -> Aabb {
+        Aabb::new(Point2::new(10.0, 10.0), 10.0, 10.0)
+    }
+
+    fn ray(x: f32, y: f32, dx: f32, dy: f32) -> Ray {
+        Ray {
+            origin: Point2::new(x, y),
+            direction: Vector2::new(dx, dy).normalize(),
+        }
+    }
+
+    #[test]
+    fn ray_parallel_to_a_side() {
+        assert_eq!(ray(0.0, 15.0, 1.0, 0.0).hits(&square()), Some(10.0));
+        assert_eq!(ray(15.0, 40.0, 0.0, -1.0).hits(&square()), Some(20.0));
+        assert_eq!(ray(0.0, 30.0, 1.0, 0.0).hits(&square()), None);
+        // Grazing an edge doesn't count.
+        assert_eq!(ray(0.0, 10.0, 1.0, 0.0).hits(&square()), None);
+        assert_eq!(ray(0.0, 20.0, 1.0, 0.0).hits(&square()), None);
+    }
+
+    #[test]
+    fn ray_starting_inside() {
+        assert_eq!(ray(15.0, 15.0, 1.0, 0.0).hits(&square()), Some(0.0));
+        assert_eq!(ray(15.0, 15.0, -1.0, 1.0).hits(&square()), Some(0.0));
+    }
+
+    #[test]
+    fn ray_pointing_away_or_passing_by() {
+        assert_eq!(ray(0.0, 15.0, -1.0, 0.0).hits(&square()), None);
+        assert_eq!(ray(0.0, 50.0, 1.0, -1.0).hits(&square()), None);
+    }
+
+    #[test]
+    fn diagonal_ray() {
+        let near = ray(0.0, 0.0, 1.0, 1.0).hits(&square()).unwrap();
+        assert!((near - 200.0f32.sqrt()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn circle_against_box() {
+        let circle = |x, y| Circle {
+            center: Point2::new(x, y),
+            radius: 6.0,
+        };
+        assert!(circle(15.0, 15.0).overlaps_aabb(&square()));
+        assert!(circle(5.0, 15.0).overlaps_aabb(&square()));
+        assert!(!circle(3.0, 15.0).overlaps_aabb(&square()));
+        // Touching isn't overlapping.
+        assert!(!circle(4.0, 15.0).overlaps_aabb(&square()));
+        // Within reach of both sides, but not of the corner.
+        assert!(!circle(25.0, 25.0).overlaps_aabb(&square()));
+        assert!(circle(24.0, 24.0).overlaps_aabb(&square()));
+    }
+}
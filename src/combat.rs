@@ -0,0 +1,247 @@
+//! Things that fly and hurt: the player's shots, the bombs enemies drop,
+//! and the player's health.
+
+use std::sync::mpsc::Sender;
+
+use ggez::graphics::{Point2, Vector2};
+
+use rand::prelude::*;
+
+use collision::Circle;
+use waves::{sine_wave, DynamicWave, WaveCommand, WaveUpdate};
+
+use SCREEN_HEIGHT;
+use SCREEN_WIDTH;
+
+pub const BOMB_RADIUS: f32 = 6.0;
+/// A falling bomb whistles down from `BOMB_HIGH` to `BOMB_LOW` Hz as it
+/// nears the ground, so it can be heard coming.
+const BOMB_HIGH: f32 = 1500.0;
+const BOMB_LOW: f32 = 300.0;
+const BOMB_AMPLITUDE: f32 = 0.08;
+pub const SHOT_RADIUS: f32 = 4.0;
+const SHOT_SPEED: f32 = 900.0;
+const SHOT_AMPLITUDE: f32 = 0.05;
+/// Seconds after being hit during which the player can't be hurt again.
+const INVULNERABLE_TIME: f32 = 1.5;
+
+pub struct Bomb {
+    pub pos: Point2,
+    velocity: Vector2,
+    sound_id: u64,
+}
+
+impl Bomb {
+    pub fn new(pos: Point2, speed: f32, swave: &Sender<WaveCommand>) -> Bomb {
+        let sound_id = random();
+        swave
+            .send(WaveCommand::Replace(
+                sound_id,
+                DynamicWave::new(BOMB_HIGH, 0.0, sine_wave),
+            ))
+            .unwrap();
+        Bomb {
+            pos,
+            velocity: Vector2::new(0.0, speed),
+            sound_id,
+        }
+    }
+
+    pub fn update(&mut self, listener_x: f32, seconds: f32, swave: &Sender<WaveCommand>) {
+        self.pos += self.velocity * seconds;
+
+        let fallen = (self.pos.y / SCREEN_HEIGHT as f32).clamp(0.0, 1.0);
+        let freq = BOMB_HIGH - fallen * (BOMB_HIGH - BOMB_LOW);
+        play_panned(self.sound_id, freq, BOMB_AMPLITUDE, self.pos.x - listener_x, swave);
+    }
+
+    pub fn landed(&self) -> bool {
+        self.pos.y - BOMB_RADIUS > SCREEN_HEIGHT as f32
+    }
+
+    pub fn circle(&self) -> Circle {
+        Circle {
+            center: self.pos,
+            radius: BOMB_RADIUS,
+        }
+    }
+
+    pub fn silence(&self, swave: &Sender<WaveCommand>) {
+        play_panned(self.sound_id, 0.0, 0.0, 0.0, swave);
+    }
+
+    pub fn destroy(self, swave: &Sender<WaveCommand>) {
+        swave.send(WaveCommand::Delete(self.sound_id)).unwrap();
+    }
+}
+
+/// A shot in flight. It sounds the player's pitch when fired, and is
+/// judged by that pitch when it strikes.
+pub struct Shot {
+    pub pos: Point2,
+    velocity: Vector2,
+    pub pitch: f32,
+    sound_id: u64,
+}
+
+impl Shot {
+    /// `direction` must be of unit length.
+    pub fn new(pos: Point2, direction: Vector2, pitch: f32, swave: &Sender<WaveCommand>) -> Shot {
+        let sound_id = random();
+        swave
+            .send(WaveCommand::Replace(
+                sound_id,
+                DynamicWave::new(pitch, 0.0, sine_wave),
+            ))
+            .unwrap();
+        Shot {
+            pos,
+            velocity: direction * SHOT_SPEED,
+            pitch,
+            sound_id,
+        }
+    }
+
+    pub fn update(&mut self, listener_x: f32, seconds: f32, swave: &Sender<WaveCommand>) {
+        self.pos += self.velocity * seconds;
+        play_panned(self.sound_id, self.pitch, SHOT_AMPLITUDE, self.pos.x - listener_x, swave);
+    }
+
+    pub fn direction(&self) -> Vector2 {
+        self.velocity.normalize()
+    }
+
+    pub fn off_screen(&self) -> bool {
+        self.pos.x < -SHOT_RADIUS
+            || self.pos.x > SCREEN_WIDTH as f32 + SHOT_RADIUS
+            || self.pos.y < -SHOT_RADIUS
+            || self.pos.y > SCREEN_HEIGHT as f32 + SHOT_RADIUS
+    }
+
+    pub fn circle(&self) -> Circle {
+        Circle {
+            center: self.pos,
+            radius: SHOT_RADIUS,
+        }
+    }
+
+    pub fn silence(&self, swave: &Sender<WaveCommand>) {
+        play_panned(self.sound_id, 0.0, 0.0, 0.0, swave);
+    }
+
+    pub fn destroy(self, swave: &Sender<WaveCommand>) {
+        swave.send(WaveCommand::Delete(self.sound_id)).unwrap();
+    }
+}
+
+/// Sounds `sound_id` at `offset` pixels to the right of the listener.
+fn play_panned(sound_id: u64, freq: f32, amplitude: f32, offset: f32, swave: &Sender<WaveCommand>) {
+    let pan = (offset / SCREEN_WIDTH as f32).clamp(-1.0, 1.0);
+    swave
+        .send(WaveCommand::Update(
+            sound_id,
+            WaveUpdate {
+                freq,
+                amp: (amplitude * (1.0 - pan) / 2.0, amplitude * (1.0 + pan) / 2.0),
+            },
+        ))
+        .unwrap();
+}
+
+/// What a hit did to the player.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Damage {
+    /// Still recovering from the last hit.
+    Ignored,
+    Hurt,
+    LifeLost,
+    Dead,
+}
+
+#[derive(Debug, Clone)]
+pub struct Health {
+    pub lives: u32,
+    pub health: u32,
+    pub max_health: u32,
+    invulnerable: f32,
+}
+
+impl Health {
+    pub fn new(lives: u32, max_health: u32) -> Health {
+        Health {
+            lives,
+            health: max_health,
+            max_health,
+            invulnerable: 0.0,
+        }
+    }
+
+    pub fn update(&mut self, seconds: f32) {
+        self.invulnerable = (self.invulnerable - seconds).max(0.0);
+    }
+
+    pub fn invulnerable(&self) -> bool {
+        self.invulnerable > 0.0
+    }
+
+    pub fn damage(&mut self) -> Damage {
+        if self.invulnerable() || self.lives == 0 {
+            return Damage::Ignored;
+        }
+        self.invulnerable = INVULNERABLE_TIME;
+        self.health -= 1;
+        if self.health > 0 {
+            return Damage::Hurt;
+        }
+        self.lives -= 1;
+        if self.lives == 0 {
+            Damage::Dead
+        } else {
+            self.health = self.max_health;
+            Damage::LifeLost
+        }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::new(3, 3)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_wear_down_health_then_lives() {
+        let mut health = Health::new(2, 2);
+        assert_eq!(health.damage(), Damage::Hurt);
+        assert_eq!(health.health, 1);
+        health.update(INVULNERABLE_TIME);
+        assert_eq!(health.damage(), Damage::LifeLost);
+        assert_eq!((health.lives, health.health), (1, 2));
+    }
+
+    #[test]
+    fn hits_while_recovering_are_ignored() {
+        let mut health = Health::new(3, 3);
+        assert_eq!(health.damage(), Damage::Hurt);
+        assert_eq!(health.damage(), Damage::Ignored);
+        health.update(INVULNERABLE_TIME / 2.0);
+        assert_eq!(health.damage(), Damage::Ignored);
+        assert_eq!(health.health, 2);
+        health.update(INVULNERABLE_TIME);
+        assert_eq!(health.damage(), Damage::Hurt);
+    }
+
+    #[test]
+    fn last_hit_reaches_zero_exactly() {
+        let mut health = Health::new(1, 1);
+        assert_eq!(health.damage(), Damage::Dead);
+        assert_eq!((health.lives, health.health), (0, 0));
+        health.update(INVULNERABLE_TIME);
+        assert_eq!(health.damage(), Damage::Ignored);
+        assert_eq!((health.lives, health.health), (0, 0));
+    }
+}
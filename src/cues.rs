@@ -0,0 +1,188 @@
+//! Sounds that stand in for what can't be seen when playing by ear alone:
+//! where the player is, how high they have jumped, when they run into a
+//! wall or land, when they shoot and when they are hit.
+
+use std::sync::mpsc::Sender;
+
+use rand::prelude::*;
+
+use waves::{sine_wave, square_wave, DynamicWave, WaveCommand, WaveUpdate};
+
+/// Low hum that pans with the player, well below any enemy's pitch. It
+/// rises by up to an octave as the player jumps.
+const POSITION_FREQ: f32 = 65.41;
+const POSITION_AMPLITUDE: f32 = 0.05;
+const WALL_FREQ: f32 = 55.0;
+const WALL_AMPLITUDE: f32 = 0.15;
+/// Seconds the bump sounds for after the player reaches a wall.
+const WALL_TIME: f32 = 0.15;
+const SHOT_FREQ: f32 = 1760.0;
+const HIT_FREQ: f32 = 2637.0;
+const BLIP_AMPLITUDE: f32 = 0.05;
+const BLIP_TIME: f32 = 0.05;
+const HURT_FREQ: f32 = 110.0;
+const HURT_TIME: f32 = 0.3;
+const LAND_FREQ: f32 = 82.41;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Wall {
+    Left,
+    Right,
+}
+
+pub struct Cues {
+    position_sound: u64,
+    wall_sound: u64,
+    blip_sound: u64,
+    /// Wall being touched, to bump only once per contact.
+    touching: Option<Wall>,
+    bump_left: f32,
+    blip_freq: f32,
+    blip_left: f32,
+}
+
+impl Cues {
+    pub fn new(swave: &Sender<WaveCommand>) -> Cues {
+        let position_sound = random();
+        let wall_sound = random();
+        let blip_sound = random();
+        swave
+            .send(WaveCommand::Replace(
+                position_sound,
+                DynamicWave::new(POSITION_FREQ, 0.0, sine_wave),
+            ))
+            .unwrap();
+        swave
+            .send(WaveCommand::Replace(
+                wall_sound,
+                DynamicWave::new(WALL_FREQ, 0.0, square_wave),
+            ))
+            .unwrap();
+        swave
+            .send(WaveCommand::Replace(
+                blip_sound,
+                DynamicWave::new(SHOT_FREQ, 0.0, sine_wave),
+            ))
+            .unwrap();
+
+        Cues {
+            position_sound,
+            wall_sound,
+            blip_sound,
+            touching: None,
+            bump_left: 0.0,
+            blip_freq: SHOT_FREQ,
+            blip_left: 0.0,
+        }
+    }
+
+    /// Click for a shot being fired, standing in for the drawn ray.
+    pub fn shot(&mut self) {
+        self.blip_freq = SHOT_FREQ;
+        self.blip_left = BLIP_TIME;
+    }
+
+    /// Click for an enemy being destroyed.
+    pub fn hit(&mut self) {
+        self.blip_freq = HIT_FREQ;
+        self.blip_left = BLIP_TIME;
+    }
+
+    /// Low thud for the player being hit.
+    pub fn hurt(&mut self) {
+        self.blip_freq = HURT_FREQ;
+        self.blip_left = HURT_TIME;
+    }
+
+    /// Thump for the player touching down after a jump.
+    pub fn land(&mut self) {
+        self.blip_freq = LAND_FREQ;
+        self.blip_left = BLIP_TIME;
+    }
+
+    /// `player` is the player's position across the screen, from 0 at the
+    /// left edge to 1 at the right, their height from 0 on the ground to 1
+    /// at the top, and the wall they are pressed against. Without it, only
+    /// shots and hits are heard.
+    pub fn update(
+        &mut self,
+        swave: &Sender<WaveCommand>,
+        player: Option<(f32, f32, Option<Wall>)>,
+        seconds: f32,
+    ) {
+        self.blip_left = (self.blip_left - seconds).max(0.0);
+        let amp = if self.blip_left > 0.0 {
+            BLIP_AMPLITUDE
+        } else {
+            0.0
+        };
+        swave
+            .send(WaveCommand::Update(
+                self.blip_sound,
+                WaveUpdate {
+                    freq: self.blip_freq,
+                    amp: (amp, amp),
+                },
+            ))
+            .unwrap();
+
+        let (x, height, wall) = match player {
+            Some((x, height, wall)) => (x.clamp(0.0, 1.0), height.clamp(0.0, 1.0), wall),
+            None => {
+                self.silence_player(swave);
+                return;
+            }
+        };
+        swave
+            .send(WaveCommand::Update(
+                self.position_sound,
+                WaveUpdate {
+                    freq: POSITION_FREQ * (1.0 + height),
+                    amp: (POSITION_AMPLITUDE * (1.0 - x), POSITION_AMPLITUDE * x),
+                },
+            ))
+            .unwrap();
+
+        if wall.is_some() && wall != self.touching {
+            self.bump_left = WALL_TIME;
+        }
+        self.touching = wall;
+        self.bump_left = (self.bump_left - seconds).max(0.0);
+
+        let amp = match self.touching {
+            Some(Wall::Left) if self.bump_left > 0.0 => (WALL_AMPLITUDE, 0.0),
+            Some(Wall::Right) if self.bump_left > 0.0 => (0.0, WALL_AMPLITUDE),
+            _ => (0.0, 0.0),
+        };
+        swave
+            .send(WaveCommand::Update(
+                self.wall_sound,
+                WaveUpdate {
+                    freq: WALL_FREQ,
+                    amp,
+                },
+            ))
+            .unwrap();
+    }
+
+    pub fn silence(&mut self, swave: &Sender<WaveCommand>) {
+        self.blip_left = 0.0;
+        self.update(swave, None, 0.0);
+    }
+
+    fn silence_player(&mut self, swave: &Sender<WaveCommand>) {
+        for &id in &[self.position_sound, self.wall_sound] {
+            swave
+                .send(WaveCommand::Update(
+                    id,
+                    WaveUpdate {
+                        freq: 0.0,
+                        amp: (0.0, 0.0),
+                    },
+                ))
+                .unwrap();
+        }
+        self.touching = None;
+        self.bump_left = 0.0;
+    }
+}
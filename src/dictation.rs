@@ -0,0 +1,189 @@
+//! Melodic dictation: a phrase from the level is played and the player
+//! writes it down by note name.
+
+use level::Note;
+use waves::notes;
+
+/// How one written note compares to the note that was played.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grade {
+    Correct,
+    /// Right note name, wrong octave.
+    WrongOctave,
+    /// Wrong note name, whatever the octave.
+    WrongPitchClass,
+    /// Nothing written for this note, or it couldn't be read.
+    Missing,
+}
+
+impl Grade {
+    fn label(&self) -> &'static str {
+        match self {
+            Grade::Correct => "ok",
+            Grade::WrongOctave => "octave",
+            Grade::WrongPitchClass => "note",
+            Grade::Missing => "missing",
+        }
+    }
+}
+
+/// Grades a written note name against the frequency that was played.
+pub fn grade(expected: f32, answer: &str) -> Grade {
+    let answer = match notes::parse_midi(answer) {
+        Some(midi) => midi,
+        None => return Grade::Missing,
+    };
+    let expected = notes::freq_to_midi(expected).round() as i32;
+    if answer == expected {
+        Grade::Correct
+    } else if (answer - expected) % 12 == 0 {
+        Grade::WrongOctave
+    } else {
+        Grade::WrongPitchClass
+    }
+}
+
+/// A phrase being played and transcribed.
+pub struct Dictation {
+    /// The phrase's notes, moved to start on beat 0.
+    pub notes: Vec<Note>,
+    /// One wave per note, switched on while the note sounds.
+    pub sounds: Vec<u64>,
+    /// Playback position in beats.
+    pub beat: f32,
+    /// What the player has typed so far.
+    pub answer: String,
+    /// Seconds into the level at which the phrase started.
+    pub asked_at: f32,
+}
+
+impl Dictation {
+    pub fn new(phrase: &[Note], sounds: Vec<u64>, asked_at: f32) -> Dictation {
+        let first = phrase.first().map_or(0.0, |n| n.start);
+        Dictation {
+            notes: phrase
+                .iter()
+                .map(|n| Note {
+                    start: n.start - first,
+                    ..n.clone()
+                })
+                .collect(),
+            sounds,
+            beat: 0.0,
+            answer: String::new(),
+            asked_at,
+        }
+    }
+
+    pub fn sounding(&self, idx: usize) -> bool {
+        let note = &self.notes[idx];
+        self.beat >= note.start && self.beat < note.start + note.beats
+    }
+
+    /// The note names written so far. They may be separated by spaces or
+    /// commas.
+    pub fn written(&self) -> Vec<&str> {
+        self.answer
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|s| !s.is_empty())
+            .collect()
+    }
+
+    /// Grades the answer note by note; extra names are ignored.
+    pub fn grade(&self) -> Vec<Grade> {
+        let written = self.written();
+        self.notes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| written.get(i).map_or(Grade::Missing, |w| grade(n.freq, w)))
+            .collect()
+    }
+
+    /// The phrase as it was played, marking each note with its grade.
+    pub fn report(&self, grades: &[Grade]) -> String {
+        let marked: Vec<String> = self
+            .notes
+            .iter()
+            .zip(grades)
+            .map(|(n, g)| match g {
+                Grade::Correct => notes::name(n.freq),
+                _ => format!("{} ({})", notes::name(n.freq), g.label()),
+            })
+            .collect();
+        marked.join("  ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// C4 D4 E4, a beat each.
+    fn dictation(answer: &str) -> Dictation {
+        let phrase: Vec<Note> = [60, 62, 64]
+            .iter()
+            .enumerate()
+            .map(|(i, &midi)| Note {
+                freq: notes::midi_to_freq(midi),
+                start: 8.0 + i as f32,
+                beats: 1.0,
+                pattern: None,
+            })
+            .collect();
+        let mut dictation = Dictation::new(&phrase, vec![1, 2, 3], 0.0);
+        dictation.answer = answer.to_owned();
+        dictation
+    }
+
+    #[test]
+    fn grades_one_note() {
+        let c4 = notes::midi_to_freq(60);
+        assert_eq!(grade(c4, "C4"), Grade::Correct);
+        assert_eq!(grade(c4, "B#3"), Grade::Correct);
+        assert_eq!(grade(c4, "C5"), Grade::WrongOctave);
+        assert_eq!(grade(c4, "C2"), Grade::WrongOctave);
+        assert_eq!(grade(c4, "C#4"), Grade::WrongPitchClass);
+        assert_eq!(grade(c4, "B3"), Grade::WrongPitchClass);
+        assert_eq!(grade(c4, "C"), Grade::Missing);
+        assert_eq!(grade(c4, "hello"), Grade::Missing);
+    }
+
+    #[test]
+    fn exact_answer() {
+        let grades = dictation("C4 D4, E4").grade();
+        assert_eq!(grades, vec![Grade::Correct; 3]);
+    }
+
+    #[test]
+    fn mixed_answer() {
+        let dictation = dictation("C5 D4 F4");
+        let grades = dictation.grade();
+        assert_eq!(
+            grades,
+            vec![Grade::WrongOctave, Grade::Correct, Grade::WrongPitchClass]
+        );
+        assert_eq!(dictation.report(&grades), "C4 (octave)  D4  E4 (note)");
+    }
+
+    #[test]
+    fn short_answer_is_missing_notes() {
+        assert_eq!(
+            dictation("C4").grade(),
+            vec![Grade::Correct, Grade::Missing, Grade::Missing]
+        );
+        assert_eq!(dictation("").grade(), vec![Grade::Missing; 3]);
+    }
+
+    #[test]
+    fn long_answer_ignores_the_extra() {
+        assert_eq!(dictation("C4 D4 E4 F4 G4").grade(), vec![Grade::Correct; 3]);
+    }
+
+    #[test]
+    fn phrase_starts_on_beat_zero() {
+        let dictation = dictation("");
+        assert_eq!(dictation.notes[0].start, 0.0);
+        assert!(dictation.sounding(0));
+        assert!(!dictation.sounding(1));
+    }
+}
@@ -0,0 +1,113 @@
+use std::io::Read;
+
+use ggez::{Context, GameError, GameResult};
+
+use toml;
+
+use level::{positive, DifficultySettings, EnemySettings};
+
+/// A player-chosen difficulty, applied on top of each level's own
+/// settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(skip)]
+    pub name: &'static str,
+    /// Multiplies enemy speeds.
+    pub enemy_speed: f32,
+    /// Multiplies how many enemies may be on screen at once.
+    pub enemy_count: f32,
+    /// Multiplies the detune of the player's cue tone. A wider detune beats
+    /// faster against the enemy and is easier to hear.
+    pub cue_detune: f32,
+    pub reload_time: f32,
+    /// Multiplies how often enemies drop bombs.
+    pub attack_rate: f32,
+    /// Highlight the enemy that should be shot next.
+    pub visual_hints: bool,
+    /// Share of notes, from 0 to 1, that adaptive difficulty aims for the
+    /// player to shoot.
+    pub target_success: f32,
+}
+
+impl Profile {
+    /// The level's settings with this profile applied.
+    pub fn apply(
+        &self,
+        enemy: &EnemySettings,
+        difficulty: &DifficultySettings,
+    ) -> (EnemySettings, DifficultySettings) {
+        let enemy = EnemySettings {
+            speed_x: enemy.speed_x * self.enemy_speed,
+            speed_y: enemy.speed_y * self.enemy_speed,
+            ..enemy.clone()
+        };
+        let difficulty = DifficultySettings {
+            max_enemies: ((difficulty.max_enemies as f32 * self.enemy_count).round() as usize)
+                .max(1),
+            cue_detune: difficulty.cue_detune * self.cue_detune,
+            reload_time: difficulty.reload_time * self.reload_time,
+            attack_rate: difficulty.attack_rate * self.attack_rate,
+            ..difficulty.clone()
+        };
+        (enemy, difficulty)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !(0.0..).contains(&self.enemy_speed) {
+            return Err("enemy_speed must not be negative".to_owned());
+        }
+        if !positive(self.enemy_count) || !positive(self.reload_time) {
+            return Err("enemy_count and reload_time must be positive".to_owned());
+        }
+        if !(0.0..).contains(&self.cue_detune) || !(0.0..).contains(&self.attack_rate) {
+            return Err("cue_detune and attack_rate must not be negative".to_owned());
+        }
+        if !(self.target_success > 0.0 && self.target_success < 1.0) {
+            return Err("target_success must be between 0 and 1".to_owned());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    easy: Profile,
+    normal: Profile,
+    hard: Profile,
+    custom: Profile,
+}
+
+/// Loads the easy, normal, hard and custom profiles, in that order.
+pub fn load(ctx: &mut Context, path: &str) -> GameResult<Vec<Profile>> {
+    let mut source = String::new();
+    ctx.filesystem.open(path)?.read_to_string(&mut source)?;
+    let file: ProfilesFile = toml::from_str(&source)
+        .map_err(|e| GameError::ResourceLoadError(format!("{}: {}", path, e)))?;
+
+    let mut profiles = vec![
+        Profile {
+            name: "Easy",
+            ..file.easy
+        },
+        Profile {
+            name: "Normal",
+            ..file.normal
+        },
+        Profile {
+            name: "Hard",
+            ..file.hard
+        },
+        Profile {
+            name: "Custom",
+            ..file.custom
+        },
+    ];
+    for profile in &mut profiles {
+        profile
+            .validate()
+            .map_err(|e| GameError::ResourceLoadError(format!("{}: {}: {}", path, profile.name, e)))?;
+    }
+    Ok(profiles)
+}
@@ -0,0 +1,207 @@
+//! Playing with a gamepad. Buttons trigger the same actions as keys, the
+//! left stick moves the player at a speed in proportion to how far it is
+//! pushed, and either trigger shoots.
+
+use ggez::event::Button;
+use sdl2::haptic::Haptic;
+use sdl2::Sdl;
+
+use bindings::Action;
+use menu::MenuInput;
+
+/// How far the stick must be pushed before it counts, as a fraction of its
+/// full travel.
+const DEAD_ZONE: f32 = 0.2;
+/// How far a trigger must be pulled to fire.
+const TRIGGER_THRESHOLD: f32 = 0.5;
+/// How far the stick must be pushed to move a menu cursor.
+const MENU_THRESHOLD: f32 = 0.5;
+const HIT_RUMBLE: f32 = 0.3;
+const HURT_RUMBLE: f32 = 1.0;
+
+/// An axis reading from -1 to 1, with the dead zone taken out so that the
+/// value starts from 0 at its edge.
+pub fn axis_value(raw: i16) -> f32 {
+    let value = (raw as f32 / 32767.0).clamp(-1.0, 1.0);
+    if value.abs() < DEAD_ZONE {
+        0.0
+    } else {
+        (value - DEAD_ZONE * value.signum()) / (1.0 - DEAD_ZONE)
+    }
+}
+
+pub fn trigger_pulled(raw: i16) -> bool {
+    axis_value(raw) > TRIGGER_THRESHOLD
+}
+
+pub fn button_action(button: Button) -> Option<Action> {
+    match button {
+        Button::DPadLeft => Some(Action::Left),
+        Button::DPadRight => Some(Action::Right),
+        Button::A => Some(Action::Jump),
+        Button::X => Some(Action::Shoot),
+        Button::Y => Some(Action::Hud),
+        Button::RightShoulder => Some(Action::PitchUp),
+        Button::LeftShoulder => Some(Action::PitchDown),
+        Button::Start => Some(Action::Pause),
+        _ => None,
+    }
+}
+
+/// Shakes the controller.
+pub trait Rumble {
+    /// `strength` from 0 to 1.
+    fn rumble(&mut self, strength: f32, seconds: f32);
+}
+
+/// For controllers that can't rumble.
+pub struct NoRumble;
+
+impl Rumble for NoRumble {
+    fn rumble(&mut self, _strength: f32, _seconds: f32) {}
+}
+
+/// Force feedback through SDL, which ggez doesn't wrap.
+pub struct SdlRumble(Haptic);
+
+impl SdlRumble {
+    /// Opens the first connected controller that can rumble, if any.
+    pub fn open(sdl: &Sdl) -> Option<SdlRumble> {
+        let haptic = sdl.haptic().ok()?;
+        let count = sdl.joystick().ok()?.num_joysticks().ok()?;
+        (0..count)
+            .filter_map(|i| haptic.open_from_joystick_id(i).ok())
+            .next()
+            .map(SdlRumble)
+    }
+}
+
+impl Rumble for SdlRumble {
+    fn rumble(&mut self, strength: f32, seconds: f32) {
+        self.0.rumble_play(strength, (seconds * 1000.0) as u32);
+    }
+}
+
+/// Rumble for the first controller that supports it, or none.
+pub fn open_rumble(sdl: &Sdl) -> Box<dyn Rumble> {
+    match SdlRumble::open(sdl) {
+        Some(rumble) => Box::new(rumble),
+        None => Box::new(NoRumble),
+    }
+}
+
+pub struct Gamepad<R: Rumble + ?Sized = dyn Rumble> {
+    pub rumble: Box<R>,
+    /// Last reading of the left stick's vertical axis.
+    stick_y: f32,
+}
+
+impl Gamepad {
+    pub fn new(rumble: Box<dyn Rumble>) -> Gamepad {
+        Gamepad {
+            rumble,
+            stick_y: 0.0,
+        }
+    }
+}
+
+impl<R: Rumble + ?Sized> Gamepad<R> {
+    /// A short buzz for shooting an enemy down.
+    pub fn hit(&mut self) {
+        self.rumble.rumble(HIT_RUMBLE, 0.1);
+    }
+
+    /// A long shake for the player being hit.
+    pub fn hurt(&mut self) {
+        self.rumble.rumble(HURT_RUMBLE, 0.4);
+    }
+
+    /// Moves a menu cursor once each time the left stick is pushed up or
+    /// down past half way.
+    pub fn stick_menu(&mut self, raw: i16) -> Option<MenuInput> {
+        let value = axis_value(raw);
+        let input = if value > MENU_THRESHOLD && self.stick_y <= MENU_THRESHOLD {
+            Some(MenuInput::Down)
+        } else if value < -MENU_THRESHOLD && self.stick_y >= -MENU_THRESHOLD {
+            Some(MenuInput::Up)
+        } else {
+            None
+        };
+        self.stick_y = value;
+        input
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Keeps every rumble asked for.
+    #[derive(Default)]
+    struct Recorder(Vec<(f32, f32)>);
+
+    impl Rumble for Recorder {
+        fn rumble(&mut self, strength: f32, seconds: f32) {
+            self.0.push((strength, seconds));
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn axis_value_removes_dead_zone() {
+        assert_eq!(axis_value(0), 0.0);
+        assert_eq!(axis_value(6000), 0.0);
+        assert_eq!(axis_value(-6000), 0.0);
+        assert!(close(axis_value(32767), 1.0));
+        assert!(close(axis_value(-32768), -1.0));
+        // Half way is 0.5 raw, rescaled from the edge of the dead zone.
+        assert!(close(axis_value(16384), 0.375));
+        assert!(close(axis_value(-16384), -0.375));
+    }
+
+    #[test]
+    fn trigger_fires_past_threshold() {
+        assert!(!trigger_pulled(0));
+        assert!(!trigger_pulled(16384));
+        assert!(trigger_pulled(30000));
+        assert!(trigger_pulled(32767));
+    }
+
+    #[test]
+    fn stick_moves_menu_once_per_push() {
+        let mut gamepad = Gamepad::new(Box::new(NoRumble));
+        assert_eq!(gamepad.stick_menu(32767), Some(MenuInput::Down));
+        assert_eq!(gamepad.stick_menu(32767), None);
+        assert_eq!(gamepad.stick_menu(30000), None);
+        assert_eq!(gamepad.stick_menu(0), None);
+        assert_eq!(gamepad.stick_menu(32767), Some(MenuInput::Down));
+        assert_eq!(gamepad.stick_menu(-32768), Some(MenuInput::Up));
+        assert_eq!(gamepad.stick_menu(-32768), None);
+        // Half way isn't far enough.
+        assert_eq!(gamepad.stick_menu(16384), None);
+    }
+
+    #[test]
+    fn buttons_map_to_actions() {
+        assert_eq!(button_action(Button::A), Some(Action::Jump));
+        assert_eq!(button_action(Button::X), Some(Action::Shoot));
+        assert_eq!(button_action(Button::Start), Some(Action::Pause));
+        assert_eq!(button_action(Button::DPadLeft), Some(Action::Left));
+        assert_eq!(button_action(Button::RightShoulder), Some(Action::PitchUp));
+        assert_eq!(button_action(Button::B), None);
+    }
+
+    #[test]
+    fn hits_and_hurts_rumble() {
+        let mut gamepad = Gamepad {
+            rumble: Box::new(Recorder::default()),
+            stick_y: 0.0,
+        };
+        gamepad.hit();
+        gamepad.hurt();
+        assert_eq!(gamepad.rumble.0, vec![(HIT_RUMBLE, 0.1), (HURT_RUMBLE, 0.4)]);
+    }
+}
@@ -0,0 +1,592 @@
+use std::fmt;
+use std::io::Read;
+
+use ggez::{Context, GameError, GameResult};
+
+use toml;
+
+use abc;
+use behaviour::Pattern;
+use midi::{MidiFile, MidiOptions};
+use musicxml;
+use training::TrainingSettings;
+use waves::notes;
+
+/// A single enemy tone. `start` and `beats` are measured in beats from the
+/// beginning of the level.
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub freq: f32,
+    pub start: f32,
+    pub beats: f32,
+    /// How this note's enemy moves, overriding the level's pattern.
+    pub pattern: Option<Pattern>,
+}
+
+/// Notes and metadata read from a music file, before level settings are
+/// applied.
+#[derive(Debug, Clone)]
+pub struct Melody {
+    pub title: Option<String>,
+    pub tempo: Option<f32>,
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnemySettings {
+    pub speed_x: f32,
+    pub speed_y: f32,
+    pub size: u32,
+    pub pattern: Pattern,
+    /// Play each chord in the note list as one enemy that sounds all of its
+    /// notes and takes a hit for each.
+    pub chord_enemies: bool,
+    /// Chance of each note's enemy sliding into its pitch.
+    pub glissando: f32,
+    /// Chance of each note's enemy being shielded, so that only shots on
+    /// the beat land.
+    pub shielded: f32,
+    /// Chance of a decoy enemy, sounding a pitch near but not in the
+    /// melody, appearing alongside each note.
+    pub phantoms: f32,
+}
+
+impl Default for EnemySettings {
+    fn default() -> EnemySettings {
+        EnemySettings {
+            speed_x: 100.0,
+            speed_y: 50.0,
+            size: 64,
+            pattern: Pattern::Bounce,
+            chord_enemies: false,
+            glissando: 0.0,
+            shielded: 0.0,
+            phantoms: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DifficultySettings {
+    pub rating: u32,
+    pub reload_time: f32,
+    pub cue_detune: f32,
+    pub max_enemies: usize,
+    /// Beats an enemy stays after its note ends before it escapes.
+    pub linger: f32,
+    /// How close, in cents, the player's tone must be to an enemy's for a
+    /// shot to land in pitch-matching mode.
+    pub pitch_tolerance: f32,
+    pub lives: u32,
+    /// Hits the player can take before losing a life.
+    pub health: u32,
+    /// Chance per second of each enemy dropping a bomb.
+    pub attack_rate: f32,
+    /// Pixels per second.
+    pub bomb_speed: f32,
+}
+
+impl Default for DifficultySettings {
+    fn default() -> DifficultySettings {
+        DifficultySettings {
+            rating: 1,
+            reload_time: 0.50,
+            cue_detune: 6.0,
+            max_enemies: 4,
+            linger: 4.0,
+            pitch_tolerance: 20.0,
+            lives: 3,
+            health: 3,
+            attack_rate: 0.05,
+            bomb_speed: 150.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Level {
+    pub title: String,
+    pub tempo: f32,
+    pub notes: Vec<Note>,
+    pub enemy: EnemySettings,
+    pub difficulty: DifficultySettings,
+    /// Chords and intervals used when the level is played in a training
+    /// mode. Each note of the level becomes the root of one question.
+    pub training: TrainingSettings,
+}
+
+/// On-disk layout of a level file, before validation. Notes are either
+/// listed inline or imported from a music file with `import`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LevelFile {
+    title: Option<String>,
+    tempo: Option<f32>,
+    notes: Option<Vec<String>>,
+    import: Option<String>,
+    track: Option<usize>,
+    channel: Option<u8>,
+    tune: Option<u32>,
+    #[serde(default)]
+    enemy: EnemySettings,
+    #[serde(default)]
+    difficulty: DifficultySettings,
+    #[serde(default)]
+    training: TrainingSettings,
+}
+
+#[derive(Debug)]
+pub enum LevelError {
+    Io(String),
+    Syntax(String),
+    Import(String),
+    Invalid(String),
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LevelError::Io(msg) => write!(f, "could not read level: {}", msg),
+            LevelError::Syntax(msg) => write!(f, "malformed level file: {}", msg),
+            LevelError::Import(msg) => write!(f, "could not import notes: {}", msg),
+            LevelError::Invalid(msg) => write!(f, "invalid level: {}", msg),
+        }
+    }
+}
+
+/// Whether `x` is above zero, which NaN isn't.
+pub fn positive(x: f32) -> bool {
+    x > 0.0
+}
+
+/// Parses a note token of the form `PITCH[:BEATS]`, where `PITCH` is a note
+/// name, several note names joined by `+` for a chord, or `R` for a rest.
+/// Returns the chord's frequencies (empty for a rest) and its length.
+fn parse_token(token: &str) -> Result<(Vec<f32>, f32, Option<Pattern>), String> {
+    let mut token = token.splitn(2, '@');
+    let note = token.next().unwrap_or("");
+    let pattern = match token.next() {
+        Some(p) => Some(
+            Pattern::parse(p).ok_or_else(|| format!("'{}' is not a movement pattern", p.trim()))?,
+        ),
+        None => None,
+    };
+
+    let mut parts = note.splitn(2, ':');
+    let pitch = parts.next().unwrap_or("").trim();
+    let beats = match parts.next() {
+        Some(b) => b
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| format!("'{}' is not a number of beats", b.trim()))?,
+        None => 1.0,
+    };
+
+    if !positive(beats) || !beats.is_finite() {
+        return Err(format!("length must be a positive number of beats, got {}", beats));
+    }
+
+    if pitch.eq_ignore_ascii_case("r") {
+        return Ok((Vec::new(), beats, pattern));
+    }
+
+    let mut freqs = Vec::new();
+    for name in pitch.split('+') {
+        match notes::parse(name) {
+            Some(freq) => freqs.push(freq),
+            None => return Err(format!("'{}' is not a note name (expected e.g. C4, F#5, Bb3)", name)),
+        }
+    }
+    Ok((freqs, beats, pattern))
+}
+
+fn parse_notes(tokens: &[String]) -> Result<Vec<Note>, LevelError> {
+    let mut notes = Vec::new();
+    let mut start = 0.0;
+    for (i, token) in tokens.iter().enumerate() {
+        let (freqs, beats, pattern) = parse_token(token)
+            .map_err(|e| LevelError::Invalid(format!("note {} ('{}'): {}", i + 1, token, e)))?;
+        for freq in freqs {
+            notes.push(Note {
+                freq,
+                start,
+                beats,
+                pattern,
+            });
+        }
+        start += beats;
+    }
+    Ok(notes)
+}
+
+/// Which part of a music file to import: the MIDI track or MusicXML part,
+/// the MIDI channel, and the ABC tune number.
+#[derive(Debug, Clone, Default)]
+struct ImportOptions {
+    track: Option<usize>,
+    channel: Option<u8>,
+    tune: Option<u32>,
+}
+
+fn is_music_file(path: &str) -> bool {
+    let extension = path.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    matches!(extension.as_str(), "mid" | "midi" | "abc" | "xml" | "musicxml")
+}
+
+/// Reads notes from a music file, choosing the format by extension.
+fn import_melody(path: &str, data: &[u8], options: &ImportOptions) -> Result<Melody, String> {
+    let extension = path.rsplit('.').next().unwrap_or("").to_ascii_lowercase();
+    let text = || String::from_utf8_lossy(data);
+    match extension.as_str() {
+        "mid" | "midi" => MidiFile::parse(data)?.melody(&MidiOptions {
+            track: options.track,
+            channel: options.channel,
+        }),
+        "abc" => abc::melody(&text(), options.tune),
+        "xml" | "musicxml" => musicxml::melody(&text(), options.track),
+        _ => Err(format!(
+            "'{}' is not a supported music file (expected .mid, .abc or .musicxml)",
+            path
+        )),
+    }
+}
+
+fn read_file(ctx: &mut Context, path: &str) -> GameResult<Vec<u8>> {
+    let mut data = Vec::new();
+    let mut f = ctx.filesystem.open(path)?;
+    f.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+impl Level {
+    /// Parses a level file. `read` fetches the contents of any music file
+    /// the level imports its notes from.
+    pub fn from_toml<F>(source: &str, mut read: F) -> Result<Level, LevelError>
+    where
+        F: FnMut(&str) -> Result<Vec<u8>, String>,
+    {
+        let file: LevelFile =
+            toml::from_str(source).map_err(|e| LevelError::Syntax(format!("{}", e)))?;
+
+        let melody = match (&file.notes, &file.import) {
+            (Some(_), Some(_)) => {
+                return Err(LevelError::Invalid(
+                    "a level may list notes or import them, not both".to_owned(),
+                ))
+            }
+            (None, None) => {
+                return Err(LevelError::Invalid(
+                    "level needs either a notes list or an import file".to_owned(),
+                ))
+            }
+            (Some(tokens), None) => Melody {
+                title: None,
+                tempo: None,
+                notes: parse_notes(tokens)?,
+            },
+            (None, Some(path)) => {
+                let data = read(path).map_err(|e| LevelError::Io(format!("{}: {}", path, e)))?;
+                let options = ImportOptions {
+                    track: file.track,
+                    channel: file.channel,
+                    tune: file.tune,
+                };
+                import_melody(path, &data, &options)
+                    .map_err(|e| LevelError::Import(format!("{}: {}", path, e)))?
+            }
+        };
+        if file.import.is_none()
+            && (file.track.is_some() || file.channel.is_some() || file.tune.is_some())
+        {
+            return Err(LevelError::Invalid(
+                "track, channel and tune only apply to imported notes".to_owned(),
+            ));
+        }
+
+        let melody = Melody {
+            title: file.title.or(melody.title),
+            tempo: file.tempo.or(melody.tempo),
+            notes: melody.notes,
+        };
+        Level::from_melody(melody, file.enemy, file.difficulty, file.training)
+    }
+
+    /// Validates a melody and combines it with level settings.
+    pub fn from_melody(
+        melody: Melody,
+        enemy: EnemySettings,
+        difficulty: DifficultySettings,
+        training: TrainingSettings,
+    ) -> Result<Level, LevelError> {
+        let title = match melody.title {
+            Some(ref t) if !t.trim().is_empty() => t.clone(),
+            _ => return Err(LevelError::Invalid("title must not be empty".to_owned())),
+        };
+        let tempo = melody
+            .tempo
+            .ok_or(LevelError::Invalid("tempo is missing".to_owned()))?;
+        if !positive(tempo) || !tempo.is_finite() {
+            return Err(LevelError::Invalid(format!(
+                "tempo must be a positive number of beats per minute, got {}",
+                tempo
+            )));
+        }
+
+        let notes = melody.notes;
+        if notes.is_empty() {
+            return Err(LevelError::Invalid("level has no notes".to_owned()));
+        }
+        if let Some(note) = notes.iter().find(|n| !positive(n.beats) || !n.beats.is_finite()) {
+            return Err(LevelError::Invalid(format!(
+                "note at beat {} must last a positive number of beats, got {}",
+                note.start, note.beats
+            )));
+        }
+        if enemy.speed_x < 0.0 || enemy.speed_y < 0.0 {
+            return Err(LevelError::Invalid("enemy speeds must not be negative".to_owned()));
+        }
+        if enemy.size < 8 || enemy.size > 256 {
+            return Err(LevelError::Invalid(format!(
+                "enemy size must be between 8 and 256, got {}",
+                enemy.size
+            )));
+        }
+        for &chance in &[enemy.glissando, enemy.shielded, enemy.phantoms] {
+            if !(0.0..=1.0).contains(&chance) {
+                return Err(LevelError::Invalid(
+                    "glissando, shielded and phantoms must be between 0 and 1".to_owned(),
+                ));
+            }
+        }
+
+        if difficulty.reload_time < 0.0 {
+            return Err(LevelError::Invalid("reload_time must not be negative".to_owned()));
+        }
+        if difficulty.max_enemies < 1 {
+            return Err(LevelError::Invalid("max_enemies must be at least 1".to_owned()));
+        }
+        if difficulty.linger < 0.0 {
+            return Err(LevelError::Invalid("linger must not be negative".to_owned()));
+        }
+        if !positive(difficulty.pitch_tolerance) {
+            return Err(LevelError::Invalid("pitch_tolerance must be positive".to_owned()));
+        }
+
+        if difficulty.lives == 0 || difficulty.health == 0 {
+            return Err(LevelError::Invalid("lives and health must be at least 1".to_owned()));
+        }
+        if !(0.0..).contains(&difficulty.attack_rate) || !positive(difficulty.bomb_speed) {
+            return Err(LevelError::Invalid(
+                "attack_rate must not be negative and bomb_speed must be positive".to_owned(),
+            ));
+        }
+
+        if training.chords.is_empty() {
+            return Err(LevelError::Invalid("training needs at least one chord".to_owned()));
+        }
+        let intervals = &training.intervals;
+        if intervals.is_empty() || intervals.iter().any(|i| !(1..=12).contains(i)) {
+            return Err(LevelError::Invalid(
+                "training intervals must be between 1 and 12 semitones".to_owned(),
+            ));
+        }
+        // Answers are picked with the number keys 1 to 9.
+        if training.choices < 2 || training.choices > 9 {
+            return Err(LevelError::Invalid(
+                "training choices must be between 2 and 9".to_owned(),
+            ));
+        }
+        if training.phrase_length == 0 {
+            return Err(LevelError::Invalid(
+                "training phrase_length must be positive".to_owned(),
+            ));
+        }
+
+        Ok(Level {
+            title,
+            tempo,
+            notes,
+            enemy,
+            difficulty,
+            training,
+        })
+    }
+
+    /// Loads and validates a level from the ggez filesystem. MIDI, ABC and
+    /// MusicXML files are imported directly with default settings; anything
+    /// else is read as a TOML level file.
+    pub fn load(ctx: &mut Context, path: &str) -> GameResult<Level> {
+        let result = if is_music_file(path) {
+            read_file(ctx, path)
+                .map_err(|e| LevelError::Io(format!("{}", e)))
+                .and_then(|data| {
+                    import_melody(path, &data, &ImportOptions::default())
+                        .map_err(LevelError::Import)
+                })
+                .and_then(|mut melody| {
+                    if melody.title.is_none() {
+                        melody.title = path.rsplit('/').next().map(|s| s.to_owned());
+                    }
+                    Level::from_melody(
+                        melody,
+                        EnemySettings::default(),
+                        DifficultySettings::default(),
+                        TrainingSettings::default(),
+                    )
+                })
+        } else {
+            read_file(ctx, path)
+                .map_err(|e| LevelError::Io(format!("{}", e)))
+                .and_then(|data| {
+                    String::from_utf8(data).map_err(|e| LevelError::Io(format!("{}", e)))
+                })
+                .and_then(|source| {
+                    Level::from_toml(&source, |p| read_file(ctx, p).map_err(|e| format!("{}", e)))
+                })
+        };
+        result.map_err(|e| GameError::ResourceLoadError(format!("{}: {}", path, e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALID: &str = "title = \"Test\"\ntempo = 120.0\nnotes = [\"C4\"]\n";
+
+    fn midi(freqs: &[f32]) -> Vec<i32> {
+        freqs
+            .iter()
+            .map(|&f| notes::freq_to_midi(f).round() as i32)
+            .collect()
+    }
+
+    fn level(source: &str) -> Result<Level, LevelError> {
+        Level::from_toml(source, |path| Err(format!("no file {}", path)))
+    }
+
+    fn is_invalid(result: Result<Level, LevelError>) -> bool {
+        matches!(result, Err(LevelError::Invalid(_)))
+    }
+
+    #[test]
+    fn note_tokens() {
+        let (freqs, beats, pattern) = parse_token("C4").unwrap();
+        assert_eq!((midi(&freqs), beats, pattern), (vec![60], 1.0, None));
+        let (freqs, beats, _) = parse_token("C4+E4+G4:2").unwrap();
+        assert_eq!((midi(&freqs), beats), (vec![60, 64, 67], 2.0));
+        let (freqs, beats, _) = parse_token("r:0.5").unwrap();
+        assert_eq!((freqs.len(), beats), (0, 0.5));
+        let (freqs, beats, pattern) = parse_token("Bb3:1.5@drift").unwrap();
+        assert_eq!((midi(&freqs), beats), (vec![58], 1.5));
+        assert_eq!(pattern, Some(Pattern::Drift));
+    }
+
+    #[test]
+    fn malformed_note_tokens() {
+        let tokens = [
+            "C4:", "C4:x", "C4:0", "C4:-1", "C4:inf", "C4:NaN", "H4", "C4+", "C4@spin",
+        ];
+        for token in &tokens {
+            assert!(parse_token(token).is_err(), "{}", token);
+        }
+    }
+
+    #[test]
+    fn notes_follow_each_other() {
+        let tokens: Vec<String> = ["C4:2", "R", "D4+F4"].iter().map(|&t| t.to_owned()).collect();
+        let starts: Vec<f32> = parse_notes(&tokens).unwrap().iter().map(|n| n.start).collect();
+        assert_eq!(starts, vec![0.0, 3.0, 3.0]);
+    }
+
+    #[test]
+    fn valid_level() {
+        let level = level(VALID).unwrap();
+        assert_eq!(level.title, "Test");
+        assert_eq!(level.tempo, 120.0);
+        assert_eq!(level.notes.len(), 1);
+    }
+
+    #[test]
+    fn notes_and_import_are_checked() {
+        let cases = [
+            "title = \"Test\"\ntempo = 120.0\nnotes = [\"C4\"]\nimport = \"a.mid\"",
+            "title = \"Test\"\ntempo = 120.0",
+            "title = \"Test\"\ntempo = 120.0\nnotes = []",
+            "title = \"Test\"\ntempo = 120.0\nnotes = [\"C4:0\"]",
+            "title = \" \"\ntempo = 120.0\nnotes = [\"C4\"]",
+            "tempo = 120.0\nnotes = [\"C4\"]",
+            "title = \"Test\"\nnotes = [\"C4\"]",
+            "title = \"Test\"\ntempo = 0.0\nnotes = [\"C4\"]",
+        ];
+        for source in &cases {
+            assert!(is_invalid(level(source)), "{}", source);
+        }
+        assert!(is_invalid(level(&format!("{}channel = 1", VALID))));
+    }
+
+    #[test]
+    fn settings_are_checked() {
+        let cases = [
+            "[enemy]\nspeed_x = -1.0",
+            "[enemy]\nsize = 4",
+            "[enemy]\nsize = 300",
+            "[enemy]\nglissando = 1.5",
+            "[enemy]\nphantoms = -0.1",
+            "[difficulty]\nreload_time = -1.0",
+            "[difficulty]\nmax_enemies = 0",
+            "[difficulty]\nlinger = -1.0",
+            "[difficulty]\npitch_tolerance = 0.0",
+            "[difficulty]\nlives = 0",
+            "[difficulty]\nhealth = 0",
+            "[difficulty]\nattack_rate = -1.0",
+            "[difficulty]\nbomb_speed = 0.0",
+            "[training]\nchords = []",
+            "[training]\nintervals = []",
+            "[training]\nintervals = [13]",
+            "[training]\nchoices = 1",
+            "[training]\nchoices = 10",
+            "[training]\nphrase_length = 0",
+        ];
+        for extra in &cases {
+            assert!(is_invalid(level(&format!("{}{}", VALID, extra))), "{}", extra);
+        }
+    }
+
+    #[test]
+    fn missing_import_is_an_io_error() {
+        let source = "title = \"Test\"\ntempo = 120.0\nimport = \"a.mid\"";
+        match level(source) {
+            Err(LevelError::Io(_)) => (),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn imported_notes_need_a_length() {
+        let note = |beats| Note {
+            freq: 440.0,
+            start: 0.0,
+            beats,
+            pattern: None,
+        };
+        let melody = |beats| Melody {
+            title: Some("Test".to_owned()),
+            tempo: Some(120.0),
+            notes: vec![note(1.0), note(beats)],
+        };
+        let from_melody = |beats| {
+            Level::from_melody(
+                melody(beats),
+                EnemySettings::default(),
+                DifficultySettings::default(),
+                TrainingSettings::default(),
+            )
+        };
+        assert!(from_melody(1.0).is_ok());
+        assert!(is_invalid(from_melody(0.0)));
+        assert!(is_invalid(from_melody(-1.0)));
+    }
+}
@@ -1,40 +1,122 @@
-//! Basic hello world example.
+//! Tonal: shoot the notes you hear.
 
 extern crate ggez;
 extern crate rand;
 extern crate rodio;
+extern crate sdl2;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
+extern crate xml;
 
 use std::env;
+use std::io::Write;
 use std::path;
 use std::sync::mpsc::Sender;
 
 use rand::prelude::*;
 
-use ggez::conf;
 use ggez::conf::{WindowMode, WindowSetup};
 use ggez::event;
-use ggez::event::Keycode;
+use ggez::event::{Axis, Button, Keycode, MouseButton, MouseState};
 use ggez::event::Mod;
 use ggez::graphics;
 use ggez::graphics::Point2;
 use ggez::graphics::Vector2;
 use ggez::timer;
-use ggez::{Context, ContextBuilder, GameResult};
+use ggez::{Context, ContextBuilder, GameError, GameResult};
 
 use ggez::nalgebra as na;
 
+mod abc;
+mod adaptive;
+mod behaviour;
+mod bindings;
+mod campaign;
+mod collision;
+mod combat;
+mod cues;
+mod dictation;
+mod difficulty;
+mod gamepad;
+mod level;
+mod menu;
+mod midi;
+mod modes;
+mod musicxml;
+mod practice;
+mod save;
+mod score;
+mod sequencer;
+mod speech;
+mod stats;
+mod training;
 mod waves;
+use adaptive::Adaptive;
+use behaviour::{Behaviour, Pattern};
+use bindings::{Action, Bindings, ACTIONS};
+use campaign::{Campaign, LevelResult};
+use collision::{Aabb, Ray};
+use combat::{Bomb, Damage, Health, Shot, BOMB_RADIUS};
+use cues::{Cues, Wall};
+use dictation::{Dictation, Grade};
+use difficulty::Profile;
+use gamepad::Gamepad;
+use level::{DifficultySettings, EnemySettings, Level};
+use menu::{MenuInput, PauseItem, Setting, StatsItem, TitleItem};
+use menu::{PAUSE_ITEMS, STATS_ITEMS, TITLE_ITEMS};
+use modes::{GameMode, Visibility};
+use save::SaveData;
+use score::Score;
+use sequencer::Sequencer;
+use speech::{CommandBackend, LogBackend, Speech};
+use stats::{Encounter, Tally};
+use training::Question;
 use waves::notes;
-use waves::{make_waves, sine_wave, DynamicWave, WaveCommand, WaveUpdate};
+use waves::{make_waves, saw_wave, sine_wave, square_wave, DynamicWave, WaveCommand, WaveUpdate};
+use waves::output_device_names;
 
 const SCREEN_WIDTH: u32 = 800;
 const SCREEN_HEIGHT: u32 = 600;
 const DESIRED_FPS: u32 = 60;
 const X_PLAYER_MAX_SPEED: f32 = 300.0;
+/// Height of the player's top edge when standing.
+const GROUND_Y: f32 = SCREEN_HEIGHT as f32 - 50.0;
+/// Pixels per second squared.
+const GRAVITY: f32 = 1800.0;
+const JUMP_SPEED: f32 = 900.0;
+/// Gravity is this many times stronger while rising with Up released, so
+/// a tap gives a short hop and holding it a full jump.
+const JUMP_CUT: f32 = 3.0;
+/// How much quieter an enemy a full screen above or below the player's ear
+/// sounds.
+const ELEVATION_FALLOFF: f32 = 0.5;
 const EAR_DIST: f32 = 250.0;
-const RELOAD_TIME: f32 = 0.50;
-const VISIBLE_TIME: f32 = 0.10;
+/// Length of the streak drawn for a shot in flight.
+const SHOT_LENGTH: f32 = 24.0;
 const TARGET_AMPLITUDE: f32 = 0.20;
+const INTRO_TIME: f32 = 2.0;
+const RELOAD_BAR_WIDTH: f32 = 150.0;
+const BINDINGS_PATH: &str = "/bindings.toml";
+const PRACTICE_LINE: &str = "Practice your weakest notes";
+const STATS_CSV_PATH: &str = "/stats.csv";
+const SESSIONS_CSV_PATH: &str = "/sessions.csv";
+const STATS_JSON_PATH: &str = "/stats.json";
+/// Notes and intervals listed as the weakest on the statistics screen.
+const WEAKEST_SHOWN: usize = 3;
+/// Room for the lines of a menu, between its title and footer.
+const MENU_HEIGHT: f32 = 390.0;
+const VOLUME_STEP: f32 = 0.25;
+/// Seconds a shot enemy stays visible when enemies are hidden.
+const REVEAL_TIME: f32 = 0.5;
+/// A glissando enemy slides down this many semitones into its note, then
+/// starts again, every `GLIDE_TIME` seconds.
+const GLIDE_SEMITONES: f32 = 2.0;
+const GLIDE_TIME: f32 = 1.0;
+/// How close to the beat, in beats, a shot must be to get past a shield.
+const SHIELD_WINDOW: f32 = 0.15;
 
 #[derive(Debug)]
 struct InputState {
@@ -42,12 +124,34 @@ struct InputState {
     right: bool,
     shoot: bool,
     jump: bool,
+    /// How far the left stick is pushed, from -1 (left) to 1 (right).
+    stick: f32,
+    /// Whether the left and right triggers are pulled.
+    triggers: [bool; 2],
+    click: bool,
+    /// Where the mouse points, once it has moved. Until then shots go
+    /// straight up.
+    aim: Option<Point2>,
 }
 
-#[derive(Debug)]
+impl InputState {
+    fn shooting(&self) -> bool {
+        self.shoot || self.click || self.triggers[0] || self.triggers[1]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 enum ActorType {
     Player,
     Enemy,
+    /// Sounds every note of a chord and takes a hit for each.
+    ChordEnemy,
+    /// Keeps sliding into its note from above.
+    Glissando,
+    /// A decoy sounding a pitch that isn't in the melody.
+    Phantom,
+    /// Can only be hit on the beat.
+    Shielded,
 }
 
 #[derive(Debug)]
@@ -59,6 +163,21 @@ struct Actor {
     velocity: Vector2,
     tone: f32,
     sound_id: Option<u64>,
+    /// Beat at which an enemy escapes if it hasn't been shot.
+    expires: Option<f32>,
+    /// Seconds into the level at which the actor appeared.
+    spawned_at: f32,
+    /// Whether the player is meant to shoot this enemy: any melody note,
+    /// but only the chord tone asked for.
+    target: bool,
+    behaviour: Option<Behaviour>,
+    /// A chord enemy's other voices, as sound id and pitch.
+    chord: Vec<(u64, f32)>,
+    /// For statistics: semitones from the note before, seconds until the
+    /// player first aimed at it, and pitches shot while it was due next.
+    interval: Option<i32>,
+    found_after: Option<f32>,
+    wrong: Vec<f32>,
 }
 
 fn create_player(swave: &Sender<WaveCommand>) -> Actor {
@@ -71,40 +190,122 @@ fn create_player(swave: &Sender<WaveCommand>) -> Actor {
         .unwrap();
     Actor {
         tag: ActorType::Player,
-        pos: Point2::new(0.0, SCREEN_HEIGHT as f32 - 50.0),
+        pos: Point2::new(0.0, GROUND_Y),
         velocity: na::zero(),
         width: 32,
         height: 32,
         tone: notes::A4,
         sound_id: Some(sound_id),
+        expires: None,
+        spawned_at: 0.0,
+        target: false,
+        behaviour: None,
+        chord: Vec::new(),
+        interval: None,
+        found_after: None,
+        wrong: Vec::new(),
     }
 }
 
-fn create_enemy(swave: &Sender<WaveCommand>, note: f32) -> Actor {
+fn create_enemy(
+    swave: &Sender<WaveCommand>,
+    tag: ActorType,
+    note: f32,
+    settings: &EnemySettings,
+    pattern: Pattern,
+) -> Actor {
     let sound_id = random();
 
+    // Each kind of enemy has its own timbre.
+    let wave = match tag {
+        ActorType::Glissando => saw_wave,
+        ActorType::Shielded => square_wave,
+        _ => sine_wave,
+    };
+
     //Main tone
     swave
-        .send(WaveCommand::Replace(
-            sound_id,
-            DynamicWave::new(440.0, 0.0, sine_wave),
-        ))
+        .send(WaveCommand::Replace(sound_id, DynamicWave::new(440.0, 0.0, wave)))
         .unwrap();
 
+    let pos = Point2::new(
+        (random::<u32>() % SCREEN_WIDTH) as f32,
+        (random::<u32>() % SCREEN_HEIGHT) as f32,
+    );
+    let velocity = Vector2::new(
+        random::<f32>() * settings.speed_x,
+        random::<f32>() * settings.speed_y,
+    );
     Actor {
-        tag: ActorType::Enemy,
-        pos: Point2::new(
-            (random::<u32>() % SCREEN_WIDTH) as f32,
-            (random::<u32>() % SCREEN_HEIGHT) as f32,
-        ),
-        velocity: Vector2::new(
-            random::<u32>() as f32 % 100.0,
-            random::<u32>() as f32 % 50.0,
-        ),
-        width: 64,
-        height: 64,
+        tag,
+        pos,
+        velocity,
+        width: settings.size,
+        height: settings.size,
         tone: note,
         sound_id: Some(sound_id),
+        expires: None,
+        spawned_at: 0.0,
+        target: false,
+        behaviour: Some(Behaviour::new(pattern, pos, velocity)),
+        chord: Vec::new(),
+        interval: None,
+        found_after: None,
+        wrong: Vec::new(),
+    }
+}
+
+/// Adds another note to a chord enemy.
+fn add_voice(enemy: &mut Actor, freq: f32, swave: &Sender<WaveCommand>) {
+    let sound_id = random();
+    swave
+        .send(WaveCommand::Replace(sound_id, DynamicWave::new(freq, 0.0, sine_wave)))
+        .unwrap();
+    enemy.chord.push((sound_id, freq));
+}
+
+/// Silences the chord voice nearest to `freq`, or the top voice if no pitch
+/// is given, and returns that voice's pitch. The enemy's main tone is
+/// replaced if that is the one removed.
+fn remove_voice(enemy: &mut Actor, freq: Option<f32>, swave: &Sender<WaveCommand>) -> f32 {
+    let mut voices = vec![(enemy.sound_id.unwrap(), enemy.tone)];
+    voices.append(&mut enemy.chord);
+    let distance = |v: &(u64, f32)| match freq {
+        Some(f) => modes::cents(v.1, f).abs(),
+        None => -v.1,
+    };
+    let nearest = (0..voices.len())
+        .min_by(|&a, &b| distance(&voices[a]).partial_cmp(&distance(&voices[b])).unwrap())
+        .unwrap();
+    let (sound_id, removed) = voices.remove(nearest);
+    swave.send(WaveCommand::Delete(sound_id)).unwrap();
+
+    let (sound_id, tone) = voices.remove(0);
+    enemy.sound_id = Some(sound_id);
+    enemy.tone = tone;
+    enemy.chord = voices;
+    removed
+}
+
+/// A pitch a few semitones either side of `freq`, for a phantom to sound.
+fn decoy_pitch(freq: f32) -> f32 {
+    let mut rng = thread_rng();
+    let semitones = rng.gen_range(1, 4) as f32;
+    let sign = if rng.gen() { 1.0 } else { -1.0 };
+    freq * 2.0f32.powf(sign * semitones / 12.0)
+}
+
+/// Gives a formation enemy the first place in the formation that no other
+/// enemy holds.
+fn join_formation(enemy: &mut Actor, enemies: &[Actor]) {
+    let taken: Vec<usize> = enemies
+        .iter()
+        .filter_map(|e| e.behaviour.as_ref())
+        .filter(|b| b.pattern == Pattern::Formation)
+        .map(|b| b.slot)
+        .collect();
+    if let Some(ref mut behaviour) = enemy.behaviour {
+        behaviour.slot = (0..).find(|s| !taken.contains(s)).unwrap();
     }
 }
 
@@ -112,6 +313,29 @@ fn destroy_enemy(enemy: Actor, swave: &Sender<WaveCommand>) {
     if let Some(sound_id) = enemy.sound_id {
         swave.send(WaveCommand::Delete(sound_id)).unwrap();
     }
+    for (sound_id, _) in enemy.chord {
+        swave.send(WaveCommand::Delete(sound_id)).unwrap();
+    }
+}
+
+/// Removes an enemy that got away. Phantoms are meant to be left alone, so
+/// they don't count against the player.
+fn escape_enemy(
+    enemy: Actor,
+    score: &mut Score,
+    encounters: &mut Vec<Encounter>,
+    swave: &Sender<WaveCommand>,
+) {
+    if enemy.tag != ActorType::Phantom {
+        score.escape();
+    }
+    if enemy.target {
+        encounters.push(enemy.encounter(enemy.tone, None));
+        for &(_, freq) in &enemy.chord {
+            encounters.push(enemy.encounter(freq, None));
+        }
+    }
+    destroy_enemy(enemy, swave);
 }
 
 impl Actor {
@@ -122,7 +346,7 @@ impl Actor {
         _world_coords: (u32, u32),
     ) -> GameResult<()> {
         //let (sh, sw) = world_coords;
-        let image = assets.actor_image(self);
+        let image = assets.actor_image(self.tag);
         let drawparams = graphics::DrawParam {
             dest: self.pos,
             rotation: 0.0,
@@ -132,17 +356,36 @@ impl Actor {
         graphics::draw_ex(ctx, image, drawparams)
     }
 
+    /// How the player dealt with one of the enemy's notes.
+    fn encounter(&self, freq: f32, hit_after: Option<f32>) -> Encounter {
+        Encounter {
+            freq,
+            interval: self.interval,
+            found_after: self.found_after,
+            hit_after,
+            wrong: self.wrong.clone(),
+        }
+    }
+
     fn center(&self) -> Point2 {
         Point2::new(
             self.pos.x + self.width as f32 / 2.0,
             self.pos.y + self.height as f32 / 2.0,
         )
     }
+
+    fn bounds(&self) -> Aabb {
+        Aabb::new(self.pos, self.width as f32, self.height as f32)
+    }
 }
 
 struct Assets {
     player_image: graphics::Image,
     enemy_image: graphics::Image,
+    chord_image: graphics::Image,
+    glissando_image: graphics::Image,
+    phantom_image: graphics::Image,
+    shielded_image: graphics::Image,
     ray_image: graphics::Image,
 }
 
@@ -150,19 +393,31 @@ impl Assets {
     fn new(ctx: &mut Context) -> GameResult<Assets> {
         let player_image = graphics::Image::new(ctx, "/player.png")?;
         let enemy_image = graphics::Image::new(ctx, "/rock64.png")?;
+        let chord_image = graphics::Image::new(ctx, "/chord64.png")?;
+        let glissando_image = graphics::Image::new(ctx, "/glissando64.png")?;
+        let phantom_image = graphics::Image::new(ctx, "/phantom64.png")?;
+        let shielded_image = graphics::Image::new(ctx, "/shielded64.png")?;
         let ray_image = graphics::Image::new(ctx, "/ray.png")?;
 
         Ok(Assets {
             player_image,
             enemy_image,
+            chord_image,
+            glissando_image,
+            phantom_image,
+            shielded_image,
             ray_image,
         })
     }
 
-    fn actor_image(&mut self, actor: &Actor) -> &mut graphics::Image {
-        match actor.tag {
+    fn actor_image(&mut self, tag: ActorType) -> &mut graphics::Image {
+        match tag {
             ActorType::Player => &mut self.player_image,
             ActorType::Enemy => &mut self.enemy_image,
+            ActorType::ChordEnemy => &mut self.chord_image,
+            ActorType::Glissando => &mut self.glissando_image,
+            ActorType::Phantom => &mut self.phantom_image,
+            ActorType::Shielded => &mut self.shielded_image,
         }
     }
 }
@@ -174,80 +429,135 @@ impl Default for InputState {
             right: false,
             shoot: false,
             jump: false,
+            stick: 0.0,
+            triggers: [false, false],
+            click: false,
+            aim: None,
         }
     }
 }
 
 struct Gun {
     time_to_reload: f32,
-    visible: bool,
 }
 
 impl Default for Gun {
     fn default() -> Gun {
         Gun {
             time_to_reload: 0.0,
-            visible: false,
         }
     }
 }
 
-impl Gun {
-    fn draw(&self, player: &Actor, assets: &mut Assets, ctx: &mut Context) -> GameResult<()> {
-        if self.visible {
-            let image = &assets.ray_image;
-            let drawparams = graphics::DrawParam {
-                dest: player.pos
-                    + Vector2::new(
-                        player.width as f32 / 2.0 - image.width() as f32 / 2.0,
-                        -1000.0,
-                    ),
-                rotation: 0.0,
-                offset: graphics::Point2::new(0.0, 0.0),
-                ..Default::default()
-            };
-            graphics::draw_ex(ctx, image, drawparams)
-        } else {
-            Ok(())
-        }
-    }
+/// Draws a shot as a short streak of the ray image, turned to the way it
+/// flies.
+fn draw_shot(shot: &Shot, assets: &mut Assets, ctx: &mut Context) -> GameResult<()> {
+    let image = &assets.ray_image;
+    let direction = shot.direction();
+    let drawparams = graphics::DrawParam {
+        dest: shot.pos,
+        rotation: direction.x.atan2(-direction.y),
+        offset: graphics::Point2::new(0.5, 0.0),
+        scale: graphics::Point2::new(1.0, SHOT_LENGTH / image.height() as f32),
+        ..Default::default()
+    };
+    graphics::draw_ex(ctx, image, drawparams)
 }
 
-struct Level {
-    notes: Vec<f32>,
+/// Which screen the game is showing. Menus keep the index of their
+/// highlighted line.
+enum Scene {
+    Title { cursor: usize },
+    LevelSelect { cursor: usize },
+    /// `paused` when opened from the pause menu, which it returns to.
+    Settings { cursor: usize, paused: bool },
+    /// Rebinding keys; `waiting` for the new key of the highlighted action.
+    Controls { cursor: usize, paused: bool, waiting: bool },
+    /// `message` says how the last export went.
+    Stats { cursor: usize, message: Option<String> },
+    Intro { time_left: f32 },
+    Playing,
+    Pause { cursor: usize },
+    Complete { result: LevelResult, new_best: bool },
+    GameOver { result: LevelResult },
 }
 
-impl Default for Level {
-    fn default() -> Level {
-        use notes::*;
+impl Scene {
+    fn cursor(&self) -> Option<usize> {
+        match *self {
+            Scene::Title { cursor }
+            | Scene::LevelSelect { cursor }
+            | Scene::Settings { cursor, .. }
+            | Scene::Controls { cursor, .. }
+            | Scene::Stats { cursor, .. }
+            | Scene::Pause { cursor } => Some(cursor),
+            _ => None,
+        }
+    }
 
-        let mut vie_en_rose = vec![
-            //Hold me close and hold me fast
-            C5, B4, A4, G4, E4, C5, B4, //This magic spell you cast
-            A4, G4, E4, C4, B4, A4, //This is la vie en rose
-            G4, E4, C4, C4, B4, A4, G4, //When you kiss me heaven sighs
-            C5, B4, A4, G4, E4, C5, B4, //And though I close my eyes
-            A4, G4, E4, C4, B4, A4, //I see la vie en rose
-            G4, E4, C4, C4, B4, A4, G4, //When you press me to your heart
-            C5, B4, A4, G4, E4, C5, B4, //I'm in a world apart
-            A4, G4, E4, C4, B4, A4, //A world where roses bloom
-            G4, E4, C4, C5, C5, C5, //And when you speak angels sing from above
-            D5, D5, C5, D5, D5, C5, D5, D5, C5, G4,
-            //Everyday words seem to turn into love songs
-            D5, D5, C5, D5, D5, C5, D5, D5, C5, E5, D5, //Give your heart and soul to me
-            C5, B4, A4, G4, E4, C5, B4, //And life will always be
-            A4, G4, E4, C4, B4, A4, //La vie en rose
-            G4, A4, B4, C5,
-        ];
-        vie_en_rose.reverse();
+    fn stop_waiting(&mut self) {
+        if let Scene::Controls {
+            ref mut waiting, ..
+        } = *self
+        {
+            *waiting = false;
+        }
+    }
 
-        Level { notes: vie_en_rose }
+    fn set_cursor(&mut self, to: usize) {
+        match *self {
+            Scene::Title { ref mut cursor }
+            | Scene::LevelSelect { ref mut cursor }
+            | Scene::Settings { ref mut cursor, .. }
+            | Scene::Controls { ref mut cursor, .. }
+            | Scene::Stats { ref mut cursor, .. }
+            | Scene::Pause { ref mut cursor } => *cursor = to,
+            _ => (),
+        }
     }
 }
 
+/// Progress through the level being played.
+#[derive(Default)]
+struct Run {
+    level: usize,
+    sequencer: Sequencer,
+    score: Score,
+    seconds: f32,
+    /// The level's settings with the difficulty profile applied.
+    enemy: EnemySettings,
+    difficulty: DifficultySettings,
+    /// Training modes: the question being asked, the next level note to
+    /// ask about, and how the last question went.
+    question: Option<Question>,
+    next_root: usize,
+    feedback: Option<String>,
+    /// Dictation: the phrase being transcribed and the mistakes so far.
+    dictation: Option<Dictation>,
+    wrong_notes: usize,
+    wrong_octaves: usize,
+    /// Where enemies were shot, and seconds left to show them there.
+    reveals: Vec<(Point2, ActorType, f32)>,
+    health: Health,
+    bombs: Vec<Bomb>,
+    /// Pitch of the last enemy shot down.
+    last_hit: Option<f32>,
+    shots: Vec<Shot>,
+    /// Melody note spawned last, to measure the interval to the next.
+    last_note: Option<f32>,
+    /// For statistics, added to the player's once the level is over.
+    encounters: Vec<Encounter>,
+    /// A practice level played in place of the campaign level.
+    practice: Option<Level>,
+    adaptive: Option<Adaptive>,
+    /// Encounters adaptive difficulty has taken into account.
+    adapted: usize,
+}
+
 // First we make a structure to contain the game's state
 struct MainState {
-    text: graphics::Text,
+    font: graphics::Font,
+    small_font: graphics::Font,
     assets: Assets,
     frames: usize,
     input: InputState,
@@ -255,7 +565,27 @@ struct MainState {
     enemies: Vec<Actor>,
     swave: Sender<WaveCommand>,
     gun: Gun,
-    levels: Vec<Level>,
+    campaign: Campaign,
+    run: Run,
+    scene: Scene,
+    mode: GameMode,
+    profiles: Vec<Profile>,
+    profile: usize,
+    visibility: Visibility,
+    cues: Cues,
+    speech: Speech,
+    show_fps: bool,
+    /// Adjust campaign levels to how the player is doing. Practice levels
+    /// always are.
+    adaptive: bool,
+    volume: f32,
+    /// Audio output chosen in the settings, used from the next start.
+    device: Option<String>,
+    save: SaveData,
+    /// Whether the save is out of date, to be written on the next update.
+    unsaved: bool,
+    bindings: Bindings,
+    gamepad: Gamepad,
 }
 
 impl MainState {
@@ -263,236 +593,2047 @@ impl MainState {
         // The ttf file will be in your resources directory. Later, we
         // will mount that directory so we can omit it in the path here.
         let font = graphics::Font::new(ctx, "/DejaVuSerif.ttf", 48)?;
-        let text = graphics::Text::new(ctx, "Hello world!", &font)?;
+        let small_font = graphics::Font::new(ctx, "/DejaVuSerif.ttf", 24)?;
+
+        let save = SaveData::load(ctx);
+        let prefs = save.preferences.clone();
+
+        let swave = make_waves(prefs.device.as_deref());
+        swave.send(WaveCommand::Volume(prefs.volume)).unwrap();
+
+        // Set TONAL_TTS to a speech program, e.g. `espeak` or `say`, to have
+        // the game read itself aloud. Otherwise announcements are written
+        // to speech.log in the user data directory once speech is turned on.
+        let mut speech = match env::var("TONAL_TTS") {
+            Ok(command) => Speech::new(Box::new(CommandBackend::new(&command)), true),
+            Err(_) => Speech::new(
+                Box::new(LogBackend::new(ctx.filesystem.create("/speech.log")?)),
+                false,
+            ),
+        };
+
+        if let Some(enabled) = prefs.speech {
+            speech.enabled = enabled;
+        }
 
-        let swave = make_waves();
+        let bindings = Bindings::load(ctx, BINDINGS_PATH).unwrap_or_else(|e| {
+            speech.say(&format!("Using the default controls. {}", e));
+            Bindings::default()
+        });
 
-        let mut s = MainState {
-            text,
+        let mut campaign = Campaign::load(ctx, "/levels/campaign.toml")?;
+        save.restore(&mut campaign);
+        let profiles = difficulty::load(ctx, "/difficulty.toml")?;
+        let profile = profiles
+            .iter()
+            .position(|p| p.name == prefs.difficulty)
+            .unwrap_or(1);
+
+        let s = MainState {
+            font,
+            small_font,
             frames: 0,
             input: InputState::default(),
             player: create_player(&swave),
+            cues: Cues::new(&swave),
             enemies: Vec::new(),
             assets: Assets::new(ctx)?,
             swave,
             gun: Gun::default(),
-            levels: vec![Level::default()],
+            campaign,
+            run: Run::default(),
+            scene: Scene::Title { cursor: 0 },
+            mode: prefs.mode,
+            profiles,
+            profile,
+            visibility: prefs.visibility,
+            speech,
+            show_fps: prefs.show_fps,
+            adaptive: prefs.adaptive,
+            volume: prefs.volume,
+            device: prefs.device,
+            save,
+            unsaved: false,
+            bindings,
+            gamepad: Gamepad::new(gamepad::open_rumble(&ctx.sdl_context)),
         };
         Ok(s)
     }
-}
 
-// Then we implement the `ggez:event::EventHandler` trait on it, which
-// requires callbacks for updating and drawing the game state each frame.
-//
-// The `EventHandler` trait also contains callbacks for event handling
-// that you can override if you wish, but the defaults are fine.
-impl event::EventHandler for MainState {
-    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
-        while timer::check_update_time(ctx, DESIRED_FPS) {
-            let seconds = 1.0 / (DESIRED_FPS as f32);
+    fn level(&self) -> &Level {
+        current_level(&self.campaign, &self.run.practice, self.run.level)
+    }
 
-            if self.enemies.len() < 1 && self.levels.len() > 0 {
-                if let Some(note) = self.levels[0].notes.pop() {
-                    self.enemies.push(create_enemy(&self.swave, note));
-                }
-            }
+    fn profile(&self) -> &Profile {
+        &self.profiles[self.profile]
+    }
 
-            handle_player_input(&mut self.player, &self.input, seconds);
+    /// Whether sounds should stand in for what is drawn: the player's
+    /// position, shots, hits and hints.
+    fn audio_cues(&self) -> bool {
+        self.visibility != Visibility::Shown || self.speech.enabled
+    }
 
-            apply_motion(&mut self.player, seconds);
-            apply_walls(&mut self.player, false);
+    fn entry_line(&self, idx: usize) -> String {
+        let entry = &self.campaign.entries[idx];
+        let mut line = format!("{}. {}", idx + 1, entry.level.title);
+        if !self.campaign.is_unlocked(idx) {
+            line.push_str("  (locked)");
+        } else if let Some(ref best) = entry.best {
+            line.push_str(&format!(
+                "  best {} ({:.0}%)",
+                best.score.points,
+                best.accuracy() * 100.0
+            ));
+        }
+        line
+    }
 
-            //if (self.frames % 100) == 0 {
-            for e in &mut self.enemies {
-                apply_motion(e, seconds);
-                apply_walls(e, true);
-                update_enemy_sound(&self.player, e, &self.swave);
-            }
-            // }
+    fn mode_help(&self) -> Option<String> {
+        let key = |action| self.bindings.key(action).name();
+        match self.mode {
+            GameMode::Classic => None,
+            GameMode::PitchMatch => Some(format!(
+                "{}/{} or mouse wheel: semitone, {}/{}: fine tune",
+                key(Action::PitchUp),
+                key(Action::PitchDown),
+                key(Action::FineUp),
+                key(Action::FineDown)
+            )),
+            GameMode::Chord => Some("Shoot the chord tone you are asked for".to_owned()),
+            GameMode::Interval => Some("Press the number of the interval you hear".to_owned()),
+            GameMode::Dictation => Some(
+                "Type the notes you hear, e.g. C4 E4 G4. Return: submit, Tab: replay".to_owned(),
+            ),
+        }
+    }
 
-            update_player_sound(&self.player, &self.enemies, &self.swave);
+    fn result_lines(&self, result: &LevelResult) -> Vec<String> {
+        let score = &result.score;
+        let reaction = match score.mean_reaction_time() {
+            Some(t) => format!("{:.2}s", t),
+            None => "-".to_owned(),
+        };
+        vec![
+            format!("Score: {}", score.points),
+            format!("Notes hit: {} of {}", score.hits, self.level().notes.len()),
+            format!("Accuracy: {:.0}%", result.accuracy() * 100.0),
+            format!(
+                "Misses: {}   Wrong targets: {}   Escaped: {}",
+                score.misses, score.wrong_targets, score.escaped
+            ),
+            format!("Best streak: {}", score.best_streak),
+            format!("Average reaction: {}", reaction),
+            format!("Time: {:.1}s", result.seconds),
+        ]
+    }
 
-            handle_shoot(self, seconds);
+    /// Reads out what the HUD shows.
+    fn speak_hud(&mut self) {
+        let score = &self.run.score;
+        let mut text = format!("Score {}, streak {}", score.points, score.streak);
+        if score.combo() > 1 {
+            text.push_str(&format!(", combo times {}", score.combo()));
         }
-        Ok(())
+        let health = &self.run.health;
+        text.push_str(&format!(", lives {}, health {}", health.lives, health.health));
+        text.push_str(&format!(", {} notes left", self.remaining_notes()));
+        if let Some(freq) = self.run.last_hit {
+            text.push_str(&format!(", last hit {}", notes::name(freq)));
+        }
+        if self.mode == GameMode::PitchMatch {
+            text.push_str(&format!(". Your tone: {}", modes::describe(self.player.tone)));
+        }
+        if let Some(ref question) = self.run.question {
+            text.push_str(&format!(". {}", question.prompt()));
+        }
+        if let Some(ref dictation) = self.run.dictation {
+            text.push_str(&format!(". Notes so far: {}", dictation.answer));
+        }
+        if self.mode == GameMode::Dictation {
+            text.push_str(&format!(
+                ". Wrong notes {}, wrong octaves {}",
+                self.run.wrong_notes, self.run.wrong_octaves
+            ));
+        }
+        self.speech.say(&text);
     }
 
-    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
-        graphics::clear(ctx);
+    fn set_feedback(&mut self, feedback: String) {
+        self.speech.say(&feedback);
+        self.run.feedback = Some(feedback);
+    }
+
+    fn clear_enemies(&mut self) {
+        for e in self.enemies.drain(..) {
+            destroy_enemy(e, &self.swave);
+        }
+        if let Some(dictation) = self.run.dictation.take() {
+            for id in dictation.sounds {
+                self.swave.send(WaveCommand::Delete(id)).unwrap();
+            }
+        }
+        for bomb in self.run.bombs.drain(..) {
+            bomb.destroy(&self.swave);
+        }
+        for shot in self.run.shots.drain(..) {
+            shot.destroy(&self.swave);
+        }
+        self.cues.silence(&self.swave);
+        let ray = self.aim_ray();
+        update_player_sound(&self.player, &ray, &self.enemies, 0.0, &self.swave);
+    }
 
-        // // Drawables are drawn from their top-left corner.
-        // let dest_point = graphics::Point2::new(10.0, 10.0);
-        // graphics::draw(ctx, &self.text, dest_point, 0.0)?;
+    fn start_level(&mut self, idx: usize) {
+        self.begin_level(idx, None);
+    }
 
-        self.player.draw(&mut self.assets, ctx, (0, 0))?;
+    /// Starts a practice level on the furthest level unlocked, drawing its
+    /// notes from every unlocked level's and favouring those the player
+    /// misses most.
+    fn start_practice(&mut self) {
+        let unlocked: Vec<usize> = (0..self.campaign.entries.len())
+            .filter(|&i| self.campaign.is_unlocked(i))
+            .collect();
+        let mut pitches: Vec<i32> = unlocked
+            .iter()
+            .flat_map(|&i| self.campaign.entries[i].level.notes.iter())
+            .map(|n| notes::freq_to_midi(n.freq).round() as i32)
+            .collect();
+        pitches.sort();
+        pitches.dedup();
+
+        // The first level is always unlocked.
+        let base = *unlocked.last().unwrap();
+        let level = &self.campaign.entries[base].level;
+        let practice = practice::practice_level(level, &pitches, &self.save.stats);
+        self.begin_level(base, Some(practice));
+    }
 
-        for e in &mut self.enemies {
-            e.draw(&mut self.assets, ctx, (0, 0))?;
+    /// Plays the current level again, or the same practice level.
+    fn restart_level(&mut self) {
+        let practice = self.run.practice.take();
+        let level = self.run.level;
+        self.begin_level(level, practice);
+    }
+
+    fn begin_level(&mut self, idx: usize, practice: Option<Level>) {
+        self.clear_enemies();
+        let (enemy, difficulty, tempo) = {
+            let level = current_level(&self.campaign, &practice, idx);
+            let (enemy, difficulty) = self.profile().apply(&level.enemy, &level.difficulty);
+            (enemy, difficulty, level.tempo)
+        };
+        let adaptive = if self.adaptive || practice.is_some() {
+            Some(Adaptive::new(self.profile().target_success))
+        } else {
+            None
+        };
+        self.run = Run {
+            level: idx,
+            sequencer: Sequencer::new(tempo),
+            health: Health::new(difficulty.lives, difficulty.health),
+            enemy,
+            difficulty,
+            practice,
+            adaptive,
+            ..Run::default()
+        };
+        self.input = InputState::default();
+        self.gun = Gun::default();
+        self.player.pos.y = GROUND_Y;
+        self.player.velocity = na::zero();
+        self.scene = Scene::Intro {
+            time_left: INTRO_TIME,
+        };
+
+        let mut intro = match self.run.practice {
+            Some(ref level) => format!("{}.", level.title),
+            None => format!("Level {}. {}.", idx + 1, self.level().title),
+        };
+        if let Some(help) = self.mode_help() {
+            intro.push_str(&format!(" {}.", help));
         }
+        self.speech.say(&intro);
+    }
 
-        self.gun.draw(&self.player, &mut self.assets, ctx)?;
+    fn leave_level(&mut self) {
+        self.clear_enemies();
+        let cursor = match self.run.practice {
+            Some(_) => self.campaign.entries.len(),
+            None => self.run.level,
+        };
+        self.scene = Scene::LevelSelect { cursor };
+        let line = format!("Level select. {}", self.menu_lines()[cursor]);
+        self.speech.say(&line);
+    }
 
-        self.frames += 1;
-        if (self.frames % 100) == 0 {
-            println!("FPS: {}", ggez::timer::get_fps(ctx));
+    fn finish_level(&mut self) {
+        self.clear_enemies();
+        let result = LevelResult {
+            score: self.run.score.clone(),
+            seconds: self.run.seconds,
+        };
+        // Practice levels don't count towards the campaign.
+        let new_best = match self.run.practice {
+            Some(_) => false,
+            None => self.campaign.record(self.run.level, result.clone()),
+        };
+        self.record_stats();
+        self.store();
+
+        let mut summary = format!("Level complete. {}.", self.result_lines(&result).join(". "));
+        if new_best {
+            summary.push_str(" New best!");
         }
+        self.speech.say(&summary);
 
-        graphics::present(ctx);
+        self.scene = Scene::Complete { result, new_best };
+    }
 
-        Ok(())
+    fn game_over(&mut self) {
+        self.clear_enemies();
+        let result = LevelResult {
+            score: self.run.score.clone(),
+            seconds: self.run.seconds,
+        };
+        self.record_stats();
+        self.store();
+        let summary = format!("Game over. {}.", self.result_lines(&result).join(". "));
+        self.speech.say(&summary);
+        self.scene = Scene::GameOver { result };
     }
 
-    // Handle key events.  These just map keyboard events
-    // and alter our input state appropriately.
-    fn key_down_event(&mut self, ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
-        match keycode {
-            Keycode::Left => {
-                self.input.left = true;
-            }
-            Keycode::Right => {
-                self.input.right = true;
+    /// Adds how the level went to the player's statistics.
+    fn record_stats(&mut self) {
+        let level = match self.run.practice {
+            Some(_) => "practice".to_owned(),
+            None => self.campaign.entries[self.run.level].file.clone(),
+        };
+        self.save.stats.record(&level, self.mode, &self.run.encounters);
+        self.run.encounters.clear();
+    }
+
+    /// Moves shots along, and settles those that strike an enemy or leave
+    /// the screen.
+    fn update_shots(&mut self, seconds: f32) {
+        let listener_x = self.player.center().x;
+        let mut i = 0;
+        while i < self.run.shots.len() {
+            self.run.shots[i].update(listener_x, seconds, &self.swave);
+            let circle = self.run.shots[i].circle();
+            let struck = self
+                .enemies
+                .iter()
+                .position(|e| circle.overlaps_aabb(&e.bounds()));
+            if let Some(struck) = struck {
+                let shot = self.run.shots.remove(i);
+                self.shot_hit(struck, shot.pitch);
+                shot.destroy(&self.swave);
+            } else if self.run.shots[i].off_screen() {
+                self.run.shots.remove(i).destroy(&self.swave);
+                self.run.score.miss();
+            } else {
+                i += 1;
             }
-            Keycode::Up => {
-                self.input.jump = true;
+        }
+    }
+
+    /// Scores a shot of the given pitch striking an enemy.
+    fn shot_hit(&mut self, struck: usize, pitch: f32) {
+        let (tag, chord, spawned_at) = {
+            let e = &self.enemies[struck];
+            (e.tag, !e.chord.is_empty(), e.spawned_at)
+        };
+        // In pitch-matching mode a chord enemy can be hit on any of its
+        // voices.
+        let in_tune = match self.mode {
+            GameMode::PitchMatch => {
+                let e = &self.enemies[struck];
+                let tolerance = self.run.difficulty.pitch_tolerance;
+                modes::cents(pitch, e.tone).abs() <= tolerance
+                    || e.chord.iter().any(|v| modes::cents(pitch, v.1).abs() <= tolerance)
             }
-            Keycode::Space => {
-                self.input.shoot = true;
+            _ => true,
+        };
+
+        let (hits, combo) = (self.run.score.hits, self.run.score.combo());
+        let target = self.target_index();
+        if target != Some(struck) {
+            self.run.score.wrong_target();
+            if let Some(t) = target {
+                let tone = self.enemies[struck].tone;
+                self.enemies[t].wrong.push(tone);
             }
-            Keycode::P => {
-                let img = graphics::screenshot(ctx).expect("Could not take screenshot");
-                img.encode(ctx, graphics::ImageFormat::Png, "/screenshot.png")
-                    .expect("Could not save screenshot");
+        } else if !in_tune {
+            self.run.score.miss();
+        } else if tag == ActorType::Shielded && self.run.sequencer.off_beat() > SHIELD_WINDOW {
+            self.run.score.miss();
+            self.speech.say("Shielded, shoot on the beat");
+        } else if chord {
+            // A chord enemy loses one voice per hit.
+            self.run.score.hit(self.run.seconds - spawned_at);
+            let pitch = match self.mode {
+                GameMode::PitchMatch => Some(pitch),
+                _ => None,
+            };
+            let removed = remove_voice(&mut self.enemies[struck], pitch, &self.swave);
+            let hit_after = self.run.seconds - spawned_at;
+            let encounter = self.enemies[struck].encounter(removed, Some(hit_after));
+            self.run.encounters.push(encounter);
+            self.run.last_hit = Some(removed);
+        } else {
+            let e = self.enemies.remove(struck);
+            let hit_after = self.run.seconds - e.spawned_at;
+            self.run.score.hit(hit_after);
+            self.run.encounters.push(e.encounter(e.tone, Some(hit_after)));
+            self.run.last_hit = Some(e.tone);
+            self.run.reveals.push((e.pos, e.tag, REVEAL_TIME));
+            destroy_enemy(e, &self.swave);
+            if self.mode == GameMode::Chord {
+                self.finish_question(true);
             }
-            Keycode::Escape => ctx.quit().unwrap(),
-            _ => (), // Do nothing
         }
-    }
 
-    fn key_up_event(&mut self, _ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
-        match keycode {
-            Keycode::Left => self.input.left = false,
-            Keycode::Right => self.input.right = false,
-            Keycode::Space => self.input.shoot = false,
-            Keycode::Up => {
-                self.input.jump = false;
+        if self.run.score.hits > hits {
+            if self.audio_cues() {
+                self.cues.hit();
+            }
+            self.gamepad.hit();
+            if self.run.score.combo() > combo {
+                let line = format!("Combo times {}", self.run.score.combo());
+                self.speech.say(&line);
             }
-            _ => (), // Do nothing
         }
     }
-}
 
-fn handle_player_input(player: &mut Actor, input: &InputState, dseconds: f32) {
-    let cont = 0.0 + if input.left { -1.0 } else { 0.0 } + if input.right { 1.0 } else { 0.0 };
-
-    player.pos.x += cont * X_PLAYER_MAX_SPEED * dseconds;
-}
+    /// Eases or tightens enemy speed and the cue's detune by how the notes
+    /// since the last call went.
+    fn adapt(&mut self) {
+        let (speed, cue_detune) = match self.run.adaptive {
+            Some(ref mut adaptive) => {
+                if self.run.adapted == self.run.encounters.len() {
+                    return;
+                }
+                for encounter in &self.run.encounters[self.run.adapted..] {
+                    adaptive.record(encounter.hit_after.is_some());
+                }
+                (adaptive.speed(), adaptive.cue_detune())
+            }
+            None => return,
+        };
+        self.run.adapted = self.run.encounters.len();
 
-fn update_enemy_sound(player: &Actor, enemy: &Actor, swave: &Sender<WaveCommand>) {
-    let _dist = (player.pos - enemy.pos).norm_squared();
-    //let amp = 1.0 - (player.pos.y - enemy.pos.y).abs() / SCREEN_HEIGHT as f32;
+        let (enemy, difficulty) = {
+            let level = self.level();
+            self.profile().apply(&level.enemy, &level.difficulty)
+        };
+        self.run.enemy.speed_x = enemy.speed_x * speed;
+        self.run.enemy.speed_y = enemy.speed_y * speed;
+        self.run.difficulty.cue_detune = difficulty.cue_detune * cue_detune;
+    }
 
-    let freq = enemy.tone;
+    /// Enemies drop bombs, and hurt the player by landing on them.
+    fn update_attacks(&mut self, seconds: f32) {
+        self.run.health.update(seconds);
 
-    let mut leftamp =
-        1.0 - (((player.center().x - EAR_DIST) - enemy.pos.x).abs() / SCREEN_WIDTH as f32);
-    leftamp *= leftamp;
-    let mut rightamp =
-        1.0 - ((player.center().x + EAR_DIST) - enemy.pos.x).abs() / SCREEN_WIDTH as f32;
-    rightamp *= rightamp;
+        let attack_chance = self.run.difficulty.attack_rate * seconds;
+        for e in &self.enemies {
+            if random::<f32>() < attack_chance {
+                let pos = Point2::new(e.center().x, e.pos.y + e.height as f32);
+                let bomb = Bomb::new(pos, self.run.difficulty.bomb_speed, &self.swave);
+                self.run.bombs.push(bomb);
+            }
+        }
 
-    // let sideamp = if player.center().x < enemy.pos.x {
-    //     (amp, 0.0)
-    // } else if player.center().x > enemy.pos.x + enemy.width as f32 {
-    //     (0.0, amp)
-    // }
-    // else {
-    //     (amp, amp)
-    // };
+        let player = self.player.bounds();
+        let mut hits = 0;
+        let mut i = 0;
+        while i < self.run.bombs.len() {
+            self.run.bombs[i].update(player.center().x, seconds, &self.swave);
+            let bomb = &self.run.bombs[i];
+            if bomb.circle().overlaps_aabb(&player) {
+                hits += 1;
+            }
+            if bomb.landed() || bomb.circle().overlaps_aabb(&player) {
+                self.run.bombs.remove(i).destroy(&self.swave);
+            } else {
+                i += 1;
+            }
+        }
 
-    //println!("{:?} {:?}", freq, amp);
+        let mut lost_target = false;
+        let mut i = 0;
+        while i < self.enemies.len() {
+            let e = &self.enemies[i];
+            let landed = e.behaviour.as_ref().is_some_and(|b| b.landed());
+            if landed || e.bounds().overlaps(&player) {
+                hits += 1;
+                let enemy = self.enemies.remove(i);
+                lost_target |= enemy.target;
+                // The other tones of a question, like phantoms, weren't
+                // there to be shot, so they don't count as escaping.
+                if enemy.target {
+                    let encounters = &mut self.run.encounters;
+                    escape_enemy(enemy, &mut self.run.score, encounters, &self.swave);
+                } else {
+                    destroy_enemy(enemy, &self.swave);
+                }
+            } else {
+                i += 1;
+            }
+        }
+        // The chord tone asked for is gone, so the question can't be
+        // answered any more.
+        if lost_target && self.mode == GameMode::Chord {
+            self.finish_question(false);
+        }
 
-    swave
-        .send(WaveCommand::Update(
-            enemy.sound_id.unwrap(),
-            WaveUpdate {
-                freq,
-                amp: (leftamp, rightamp),
-            },
-        ))
-        .unwrap();
-}
+        for _ in 0..hits {
+            self.hurt_player();
+        }
+    }
 
-fn update_player_sound(player: &Actor, enemies: &Vec<Actor>, swave: &Sender<WaveCommand>) {
-    let command = if let Some(e) = &enemies
-        .iter()
-        .find(|e| e.pos.x < player.center().x && e.pos.x + e.width as f32 > player.center().x)
-    {
-        WaveUpdate {
-            freq: e.tone + 6.0,
-            amp: (TARGET_AMPLITUDE, TARGET_AMPLITUDE),
+    fn hurt_player(&mut self) {
+        let line = match self.run.health.damage() {
+            Damage::Ignored | Damage::Dead => return,
+            Damage::Hurt => format!("Hit! Health {}", self.run.health.health),
+            Damage::LifeLost => format!("Life lost! {} left", self.run.health.lives),
+        };
+        if self.audio_cues() {
+            self.cues.hurt();
         }
-    } else {
-        WaveUpdate {
-            freq: 0.0,
-            amp: (0.0, 0.0),
+        self.gamepad.hurt();
+        self.speech.say(&line);
+    }
+
+    fn update_playing(&mut self, seconds: f32) {
+        self.run.seconds += seconds;
+
+        match self.mode {
+            GameMode::Classic | GameMode::PitchMatch => self.spawn_melody(seconds),
+            GameMode::Chord | GameMode::Interval => self.ask_question(),
+            GameMode::Dictation => self.dictate(seconds),
         }
-    };
 
-    swave
-        .send(WaveCommand::Update(player.sound_id.unwrap(), command))
-        .unwrap();
-}
+        handle_player_input(&mut self.player, &self.input, seconds);
 
-fn apply_motion(a: &mut Actor, dseconds: f32) {
-    a.pos += a.velocity.map(|i| i * dseconds);
-}
+        apply_motion(&mut self.player, seconds);
+        apply_walls(&mut self.player, false);
+        if apply_ground(&mut self.player) && self.audio_cues() {
+            self.cues.land();
+        }
 
-fn apply_walls(a: &mut Actor, gutter: bool) {
-    let bottom = if gutter { 50.0 } else { 0.0 };
+        let position = if self.audio_cues() {
+            let wall = if self.player.pos.x <= 0.0 {
+                Some(Wall::Left)
+            } else if self.player.pos.x + self.player.width as f32 >= SCREEN_WIDTH as f32 {
+                Some(Wall::Right)
+            } else {
+                None
+            };
+            let height = (GROUND_Y - self.player.pos.y) / GROUND_Y;
+            Some((self.player.center().x / SCREEN_WIDTH as f32, height, wall))
+        } else {
+            None
+        };
+        self.cues.update(&self.swave, position, seconds);
+        for reveal in &mut self.run.reveals {
+            reveal.2 -= seconds;
+        }
+        self.run.reveals.retain(|r| r.2 > 0.0);
 
-    if a.pos.x + a.width as f32 > SCREEN_WIDTH as f32 {
-        a.pos.x = (SCREEN_WIDTH - a.width) as f32;
-        a.velocity.x = -a.velocity.x
-    }
-    if a.pos.x < 0.0 {
-        a.pos.x = 0.0;
-        a.velocity.x = -a.velocity.x
-    }
-    if a.pos.y + a.height as f32 > SCREEN_HEIGHT as f32 - bottom {
-        a.pos.y = (SCREEN_HEIGHT - bottom as u32 - a.height) as f32;
-        a.velocity.y = -a.velocity.y
-    }
-    if a.pos.y < 0.0 {
-        a.pos.y = 0.0;
-        a.velocity.y = -a.velocity.y
-    }
-}
+        //if (self.frames % 100) == 0 {
+        let (player, time) = (self.player.center(), self.run.seconds);
+        let on_beat = self.run.sequencer.off_beat() <= SHIELD_WINDOW;
+        for e in &mut self.enemies {
+            if let Some(ref mut behaviour) = e.behaviour {
+                let size = e.width as f32;
+                behaviour.update(&mut e.pos, &mut e.velocity, size, player, time, seconds);
+            }
+            apply_motion(e, seconds);
+            apply_walls(e, true);
+            update_enemy_sound(&self.player, e, time, on_beat, &self.swave);
+        }
+        // }
+
+        match self.mode {
+            GameMode::Classic | GameMode::Chord => {
+                let cue_detune = self.run.difficulty.cue_detune;
+                // The spoken stand-in for the drawn hint: only the enemy to
+                // shoot next answers with a cue.
+                let cued = match self.target_index() {
+                    Some(t) if self.profile().visual_hints && self.speech.enabled => {
+                        &self.enemies[t..t + 1]
+                    }
+                    _ => &self.enemies[..],
+                };
+                let ray = self.aim_ray();
+                update_player_sound(&self.player, &ray, cued, cue_detune, &self.swave);
+            }
+            GameMode::PitchMatch => update_player_pitch_sound(&self.player, &self.swave),
+            GameMode::Interval | GameMode::Dictation => {
+                let ray = self.aim_ray();
+                update_player_sound(&self.player, &ray, &[], 0.0, &self.swave)
+            }
+        }
 
-fn handle_shoot(state: &mut MainState, dseconds: f32) {
-    state.gun.time_to_reload = f32::max(0.0, state.gun.time_to_reload - dseconds);
+        // Intervals and dictation are answered by name, not by shooting.
+        match self.mode {
+            GameMode::Interval | GameMode::Dictation => (),
+            _ => {
+                self.update_found();
+                handle_shoot(self, seconds);
+                self.update_shots(seconds);
+            }
+        }
 
-    if state.input.shoot && state.gun.time_to_reload <= 0.0 {
-        let idx = (0..state.enemies.len()).into_iter().find(|&i| {
-            let e = &state.enemies[i];
-            if e.pos.x < state.player.center().x
-                && e.pos.x + e.width as f32 > state.player.center().x
-            {
-                true
-            } else {
-                false
+        // Only modes where the player can shoot back are dangerous.
+        match self.mode {
+            GameMode::Classic | GameMode::PitchMatch | GameMode::Chord => {
+                self.update_attacks(seconds)
             }
-        });
+            GameMode::Interval | GameMode::Dictation => (),
+        }
 
-        match idx {
-            Some(i) => destroy_enemy(state.enemies.remove(i), &state.swave),
-            None => (),
-        };
+        self.adapt();
 
-        state.gun.time_to_reload = RELOAD_TIME;
+        if self.run.health.lives == 0 {
+            self.game_over();
+        } else if self.level_finished() {
+            self.finish_level();
+        }
     }
 
-    state.gun.visible = state.gun.time_to_reload > RELOAD_TIME - VISIBLE_TIME;
+    fn level_finished(&self) -> bool {
+        match self.mode {
+            GameMode::Classic | GameMode::PitchMatch => {
+                self.run.sequencer.finished(&self.level().notes)
+                    && self.enemies.iter().all(|e| e.tag == ActorType::Phantom)
+            }
+            GameMode::Chord | GameMode::Interval => {
+                self.run.question.is_none() && self.run.next_root >= self.level().notes.len()
+            }
+            GameMode::Dictation => {
+                self.run.dictation.is_none() && self.run.next_root >= self.level().notes.len()
+            }
+        }
+    }
+
+    /// Notes when the player first lines up a shot on the enemy due next.
+    fn update_found(&mut self) {
+        let target = match self.target_index() {
+            Some(t) => t,
+            None => return,
+        };
+        if aimed_at(&self.aim_ray(), &self.enemies) == Some(target) {
+            let e = &mut self.enemies[target];
+            if e.found_after.is_none() {
+                e.found_after = Some(self.run.seconds - e.spawned_at);
+            }
+        }
+    }
+
+    /// The line shots travel along: from the top of the player towards the
+    /// mouse, or straight up.
+    fn aim_ray(&self) -> Ray {
+        let origin = Point2::new(self.player.center().x, self.player.pos.y);
+        let direction = match self.input.aim {
+            Some(aim) if (aim - origin).norm() > 1.0 => (aim - origin).normalize(),
+            _ => Vector2::new(0.0, -1.0),
+        };
+        Ray { origin, direction }
+    }
+
+    /// Notes of the level not yet shot, escaped or answered.
+    fn remaining_notes(&self) -> usize {
+        let total = self.level().notes.len();
+        match self.mode {
+            GameMode::Classic | GameMode::PitchMatch => {
+                let alive: usize = self
+                    .enemies
+                    .iter()
+                    .filter(|e| e.tag != ActorType::Phantom)
+                    .map(|e| 1 + e.chord.len())
+                    .sum();
+                total - self.run.sequencer.started() + alive
+            }
+            GameMode::Chord | GameMode::Interval => {
+                total - self.run.next_root + self.run.question.is_some() as usize
+            }
+            GameMode::Dictation => {
+                let asking = self.run.dictation.as_ref().map_or(0, |d| d.notes.len());
+                total - self.run.next_root + asking
+            }
+        }
+    }
+
+    /// Index of the enemy a shot should hit. In melody modes enemies must be
+    /// shot in order, so this is the oldest enemy still alive that isn't a
+    /// phantom.
+    fn target_index(&self) -> Option<usize> {
+        match self.mode {
+            GameMode::Classic | GameMode::PitchMatch => {
+                self.enemies.iter().position(|e| e.tag != ActorType::Phantom)
+            }
+            GameMode::Chord => self.enemies.iter().position(|e| e.target),
+            _ => None,
+        }
+    }
+
+    fn spawn_melody(&mut self, seconds: f32) {
+        {
+            let level = current_level(&self.campaign, &self.run.practice, self.run.level);
+            let settings = &self.run.enemy;
+            let started = self.run.sequencer.advance(seconds, &level.notes);
+            let mut notes = level.notes[started].iter().peekable();
+            while let Some(note) = notes.next() {
+                let mut chord = Vec::new();
+                if settings.chord_enemies {
+                    while let Some(n) = notes.next_if(|n| n.start == note.start) {
+                        chord.push(n.freq);
+                    }
+                }
+
+                // Make room by letting the oldest enemy escape.
+                if self.enemies.len() >= self.run.difficulty.max_enemies {
+                    let enemy = self.enemies.remove(0);
+                    let encounters = &mut self.run.encounters;
+                    escape_enemy(enemy, &mut self.run.score, encounters, &self.swave);
+                }
+                let tag = if !chord.is_empty() {
+                    ActorType::ChordEnemy
+                } else if random::<f32>() < settings.glissando {
+                    ActorType::Glissando
+                } else if random::<f32>() < settings.shielded {
+                    ActorType::Shielded
+                } else {
+                    ActorType::Enemy
+                };
+                let pattern = note.pattern.unwrap_or(settings.pattern);
+                let mut enemy = create_enemy(&self.swave, tag, note.freq, settings, pattern);
+                for freq in chord {
+                    add_voice(&mut enemy, freq, &self.swave);
+                }
+                join_formation(&mut enemy, &self.enemies);
+                enemy.expires = Some(note.start + note.beats + self.run.difficulty.linger);
+                enemy.spawned_at = self.run.seconds;
+                enemy.target = true;
+                // Chords aren't reached by a single interval.
+                if enemy.chord.is_empty() {
+                    enemy.interval = self.run.last_note.map(|n| stats::semitones(n, note.freq));
+                }
+                self.run.last_note = Some(note.freq);
+                self.enemies.push(enemy);
+
+                if random::<f32>() < settings.phantoms {
+                    let decoy = decoy_pitch(note.freq);
+                    let mut phantom =
+                        create_enemy(&self.swave, ActorType::Phantom, decoy, settings, pattern);
+                    join_formation(&mut phantom, &self.enemies);
+                    phantom.expires = Some(note.start + note.beats + self.run.difficulty.linger);
+                    phantom.spawned_at = self.run.seconds;
+                    self.enemies.push(phantom);
+                }
+            }
+        }
+
+        let beat = self.run.sequencer.beat();
+        let mut i = 0;
+        while i < self.enemies.len() {
+            if self.enemies[i].expires.is_some_and(|b| beat >= b) {
+                let enemy = self.enemies.remove(i);
+                escape_enemy(enemy, &mut self.run.score, &mut self.run.encounters, &self.swave);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Sounds the next question once the previous one has been answered.
+    fn ask_question(&mut self) {
+        if self.run.question.is_some() {
+            return;
+        }
+        let level = current_level(&self.campaign, &self.run.practice, self.run.level);
+        let root = match level.notes.get(self.run.next_root) {
+            Some(note) => note.freq,
+            None => return,
+        };
+        self.run.next_root += 1;
+
+        let question = match self.mode {
+            GameMode::Chord => Question::chord(root, &level.training),
+            _ => Question::interval(root, &level.training),
+        };
+        for (tone, target) in question.tones() {
+            let pattern = self.run.enemy.pattern;
+            let mut enemy =
+                create_enemy(&self.swave, ActorType::Enemy, tone, &self.run.enemy, pattern);
+            join_formation(&mut enemy, &self.enemies);
+            enemy.target = target;
+            enemy.interval = Some(stats::semitones(root, tone));
+            enemy.spawned_at = self.run.seconds;
+            self.enemies.push(enemy);
+        }
+        self.speech.say(&question.prompt());
+        self.run.question = Some(question);
+    }
+
+    fn finish_question(&mut self, correct: bool) {
+        if let Some(question) = self.run.question.take() {
+            let verdict = if correct { "Correct!" } else { "Not quite." };
+            self.set_feedback(format!("{} {}", verdict, question.answer()));
+        }
+        for e in self.enemies.drain(..) {
+            destroy_enemy(e, &self.swave);
+        }
+    }
+
+    fn answer_interval(&mut self, choice: usize) {
+        let (tones, semitones, chosen) = match self.run.question {
+            Some(Question::Interval {
+                tones,
+                semitones,
+                ref choices,
+            }) => match choices.get(choice) {
+                Some(&c) => (tones, semitones, c),
+                None => return,
+            },
+            _ => return,
+        };
+        let correct = chosen == semitones;
+
+        let asked_at = self.enemies.first().map_or(self.run.seconds, |e| e.spawned_at);
+        let reaction = self.run.seconds - asked_at;
+        if correct {
+            self.run.score.hit(reaction);
+        } else {
+            self.run.score.wrong_target();
+        }
+        // A wrong answer counts as naming the note that interval would
+        // have led to.
+        self.run.encounters.push(Encounter {
+            freq: tones[1],
+            interval: Some(semitones as i32),
+            found_after: None,
+            hit_after: if correct { Some(reaction) } else { None },
+            wrong: if correct {
+                Vec::new()
+            } else {
+                vec![tones[0] * 2.0f32.powf(chosen as f32 / 12.0)]
+            },
+        });
+        self.finish_question(correct);
+    }
+
+    /// Plays the current phrase, starting the next one once the previous
+    /// one has been answered.
+    fn dictate(&mut self, seconds: f32) {
+        if self.run.dictation.is_none() {
+            let level = current_level(&self.campaign, &self.run.practice, self.run.level);
+            if self.run.next_root >= level.notes.len() {
+                return;
+            }
+            let end = (self.run.next_root + level.training.phrase_length).min(level.notes.len());
+            let phrase = &level.notes[self.run.next_root..end];
+            self.run.next_root = end;
+
+            let sounds = phrase
+                .iter()
+                .map(|note| {
+                    let id = random();
+                    self.swave
+                        .send(WaveCommand::Replace(
+                            id,
+                            DynamicWave::new(note.freq, 0.0, sine_wave),
+                        ))
+                        .unwrap();
+                    id
+                })
+                .collect();
+            self.run.dictation = Some(Dictation::new(phrase, sounds, self.run.seconds));
+        }
+
+        let tempo = self.level().tempo;
+        let dictation = self.run.dictation.as_mut().unwrap();
+        dictation.beat += seconds * tempo / 60.0;
+        for (i, &id) in dictation.sounds.iter().enumerate() {
+            let amp = if dictation.sounding(i) {
+                TARGET_AMPLITUDE
+            } else {
+                0.0
+            };
+            self.swave
+                .send(WaveCommand::Update(
+                    id,
+                    WaveUpdate {
+                        freq: dictation.notes[i].freq,
+                        amp: (amp, amp),
+                    },
+                ))
+                .unwrap();
+        }
+    }
+
+    fn submit_dictation(&mut self) {
+        let dictation = match self.run.dictation.take() {
+            Some(dictation) => dictation,
+            None => return,
+        };
+        for &id in &dictation.sounds {
+            self.swave.send(WaveCommand::Delete(id)).unwrap();
+        }
+
+        let grades = dictation.grade();
+        let reaction = self.run.seconds - dictation.asked_at;
+        let written = dictation.written();
+        for (i, note) in dictation.notes.iter().enumerate() {
+            let correct = grades[i] == Grade::Correct;
+            let wrong = match written.get(i).and_then(|w| notes::parse(w)) {
+                Some(freq) if !correct => vec![freq],
+                _ => Vec::new(),
+            };
+            self.run.encounters.push(Encounter {
+                freq: note.freq,
+                interval: i
+                    .checked_sub(1)
+                    .map(|p| stats::semitones(dictation.notes[p].freq, note.freq)),
+                found_after: None,
+                hit_after: if correct { Some(reaction) } else { None },
+                wrong,
+            });
+        }
+        for grade in &grades {
+            match grade {
+                Grade::Correct => self.run.score.hit(reaction),
+                Grade::WrongOctave => {
+                    self.run.wrong_octaves += 1;
+                    self.run.score.wrong_target();
+                }
+                Grade::WrongPitchClass => {
+                    self.run.wrong_notes += 1;
+                    self.run.score.wrong_target();
+                }
+                Grade::Missing => self.run.score.miss(),
+            }
+        }
+        self.set_feedback(dictation.report(&grades));
+    }
+
+    fn draw_hud(&self, ctx: &mut Context) -> GameResult<()> {
+        let score = &self.run.score;
+        let mut line = format!("Score {}   Streak {}", score.points, score.streak);
+        if score.combo() > 1 {
+            line.push_str(&format!("   x{}", score.combo()));
+        }
+        let health = &self.run.health;
+        line.push_str(&format!(
+            "   Lives {}   Health {}/{}",
+            health.lives, health.health, health.max_health
+        ));
+        draw_text(ctx, &self.small_font, &line, 10.0, 10.0)?;
+
+        let right = SCREEN_WIDTH as f32 - 200.0;
+        let left = format!("Notes left {}", self.remaining_notes());
+        draw_text(ctx, &self.small_font, &left, right, 40.0)?;
+        if let Some(freq) = self.run.last_hit {
+            let last = format!("Last hit {}", notes::name(freq));
+            draw_text(ctx, &self.small_font, &last, right, 70.0)?;
+        }
+
+        // Fills up as the gun reloads.
+        let reload_time = self.run.difficulty.reload_time;
+        let loaded = if reload_time > 0.0 {
+            1.0 - (self.gun.time_to_reload / reload_time).clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+        let bar = graphics::Rect::new(right, 110.0, RELOAD_BAR_WIDTH, 10.0);
+        graphics::rectangle(ctx, graphics::DrawMode::Line(1.0), bar)?;
+        let fill = graphics::Rect::new(right, 110.0, RELOAD_BAR_WIDTH * loaded, 10.0);
+        graphics::rectangle(ctx, graphics::DrawMode::Fill, fill)?;
+
+        if self.mode == GameMode::PitchMatch {
+            let tuning = format!("Your tone: {}", modes::describe(self.player.tone));
+            draw_text(ctx, &self.small_font, &tuning, 10.0, 40.0)?;
+        }
+        if let Some(ref question) = self.run.question {
+            draw_text(ctx, &self.small_font, &question.prompt(), 10.0, 40.0)?;
+        }
+        if let Some(ref dictation) = self.run.dictation {
+            let answer = format!("Notes: {}_", dictation.answer);
+            draw_text(ctx, &self.small_font, &answer, 10.0, 40.0)?;
+        }
+        if let Some(ref feedback) = self.run.feedback {
+            draw_text(ctx, &self.small_font, feedback, 10.0, 70.0)?;
+        }
+        if self.mode == GameMode::Dictation {
+            let mistakes = format!(
+                "Wrong notes {}   Wrong octaves {}",
+                self.run.wrong_notes, self.run.wrong_octaves
+            );
+            draw_text(ctx, &self.small_font, &mistakes, 10.0, 100.0)?;
+        }
+        Ok(())
+    }
+
+    fn draw_level_select(&self, ctx: &mut Context, cursor: usize) -> GameResult<()> {
+        draw_text_centered(ctx, &self.font, "Tonal", 40.0)?;
+
+        for i in 0..self.campaign.entries.len() {
+            let unlocked = self.campaign.is_unlocked(i);
+            let marker = if i == cursor { ">" } else { " " };
+            let line = format!("{} {}", marker, self.entry_line(i));
+
+            let color = if unlocked {
+                graphics::WHITE
+            } else {
+                graphics::Color::new(0.5, 0.5, 0.5, 1.0)
+            };
+            graphics::set_color(ctx, color)?;
+            draw_text(ctx, &self.small_font, &line, 80.0, 150.0 + i as f32 * 40.0)?;
+        }
+        graphics::set_color(ctx, graphics::WHITE)?;
+        let practice = self.campaign.entries.len();
+        let marker = if cursor == practice { ">" } else { " " };
+        let line = format!("{} {}", marker, PRACTICE_LINE);
+        draw_text(ctx, &self.small_font, &line, 80.0, 150.0 + practice as f32 * 40.0)?;
+
+        let settings = format!(
+            "{}   {}",
+            self.setting_line(Setting::Mode),
+            self.setting_line(Setting::Difficulty)
+        );
+        draw_text_centered(ctx, &self.small_font, &settings, SCREEN_HEIGHT as f32 - 100.0)?;
+        draw_text_centered(
+            ctx,
+            &self.small_font,
+            "Up/Down to choose, Enter to play, Escape to go back",
+            SCREEN_HEIGHT as f32 - 60.0,
+        )
+    }
+
+    fn draw_menu(
+        &self,
+        ctx: &mut Context,
+        title: &str,
+        cursor: usize,
+        footer: &str,
+    ) -> GameResult<()> {
+        draw_text_centered(ctx, &self.font, title, 40.0)?;
+
+        // Long menus are packed closer together to fit above the footer.
+        let lines = self.menu_lines();
+        let spacing = (MENU_HEIGHT / lines.len() as f32).min(40.0);
+        for (i, line) in lines.iter().enumerate() {
+            let marker = if i == cursor { ">" } else { " " };
+            let line = format!("{} {}", marker, line);
+            draw_text(ctx, &self.small_font, &line, 200.0, 130.0 + i as f32 * spacing)?;
+        }
+        draw_text_centered(ctx, &self.small_font, footer, SCREEN_HEIGHT as f32 - 60.0)
+    }
+
+    fn draw_intro(&self, ctx: &mut Context) -> GameResult<()> {
+        let heading = match self.run.practice {
+            Some(_) => "Practice".to_owned(),
+            None => format!("Level {}", self.run.level + 1),
+        };
+        draw_text_centered(ctx, &self.small_font, &heading, 200.0)?;
+        draw_text_centered(ctx, &self.font, &self.level().title, 250.0)?;
+        if let Some(help) = self.mode_help() {
+            draw_text_centered(ctx, &self.small_font, &help, 350.0)?;
+        }
+        Ok(())
+    }
+
+    fn draw_complete(&self, ctx: &mut Context, result: &LevelResult, new_best: bool) -> GameResult<()> {
+        draw_text_centered(ctx, &self.font, "Level complete!", 60.0)?;
+
+        for (i, line) in self.result_lines(result).iter().enumerate() {
+            draw_text_centered(ctx, &self.small_font, line, 150.0 + i as f32 * 40.0)?;
+        }
+        if new_best {
+            draw_text_centered(ctx, &self.small_font, "New best!", 450.0)?;
+        }
+
+        let next = self.run.level + 1;
+        let prompt = if self.run.practice.is_some() {
+            "Enter for another practice level, Escape for level select"
+        } else if next < self.campaign.entries.len() && self.campaign.is_unlocked(next) {
+            "Enter for the next level, Escape for level select"
+        } else {
+            "Enter to return to level select"
+        };
+        draw_text_centered(ctx, &self.small_font, prompt, SCREEN_HEIGHT as f32 - 60.0)
+    }
+
+    fn draw_game_over(&self, ctx: &mut Context, result: &LevelResult) -> GameResult<()> {
+        draw_text_centered(ctx, &self.font, "Game over", 60.0)?;
+
+        for (i, line) in self.result_lines(result).iter().enumerate() {
+            draw_text_centered(ctx, &self.small_font, line, 150.0 + i as f32 * 40.0)?;
+        }
+
+        let prompt = "Enter to try again, Escape for level select";
+        draw_text_centered(ctx, &self.small_font, prompt, SCREEN_HEIGHT as f32 - 60.0)
+    }
+}
+
+fn current_level<'a>(
+    campaign: &'a Campaign,
+    practice: &'a Option<Level>,
+    idx: usize,
+) -> &'a Level {
+    practice.as_ref().unwrap_or(&campaign.entries[idx].level)
+}
+
+fn write_file(ctx: &mut Context, path: &str, text: &str) -> GameResult<()> {
+    ctx.filesystem.create(path)?.write_all(text.as_bytes())?;
+    Ok(())
+}
+
+fn draw_text(ctx: &mut Context, font: &graphics::Font, s: &str, x: f32, y: f32) -> GameResult<()> {
+    let text = graphics::Text::new(ctx, s, font)?;
+    graphics::draw(ctx, &text, Point2::new(x, y), 0.0)
+}
+
+fn draw_text_centered(ctx: &mut Context, font: &graphics::Font, s: &str, y: f32) -> GameResult<()> {
+    let text = graphics::Text::new(ctx, s, font)?;
+    let x = (SCREEN_WIDTH as f32 - text.width() as f32) / 2.0;
+    graphics::draw(ctx, &text, Point2::new(x, y), 0.0)
+}
+
+// Then we implement the `ggez:event::EventHandler` trait on it, which
+// requires callbacks for updating and drawing the game state each frame.
+//
+// The `EventHandler` trait also contains callbacks for event handling
+// that you can override if you wish, but the defaults are fine.
+impl event::EventHandler for MainState {
+    fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
+        if self.unsaved {
+            if let Err(e) = self.save.save(ctx) {
+                self.speech.say(&format!("Could not save. {}", e));
+            }
+            self.unsaved = false;
+        }
+
+        while timer::check_update_time(ctx, DESIRED_FPS) {
+            let seconds = 1.0 / (DESIRED_FPS as f32);
+
+            let intro_over = match self.scene {
+                Scene::Intro { ref mut time_left } => {
+                    *time_left -= seconds;
+                    *time_left <= 0.0
+                }
+                Scene::Playing => {
+                    self.update_playing(seconds);
+                    false
+                }
+                _ => false,
+            };
+            if intro_over {
+                self.scene = Scene::Playing;
+            }
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult<()> {
+        graphics::clear(ctx);
+
+        match self.scene {
+            Scene::Title { cursor } => {
+                let footer = "Up/Down to choose, Enter to select";
+                self.draw_menu(ctx, "Tonal", cursor, footer)?
+            }
+            Scene::LevelSelect { cursor } => self.draw_level_select(ctx, cursor)?,
+            Scene::Settings { cursor, .. } => {
+                let footer = "Enter or Left/Right to change, Escape to go back";
+                self.draw_menu(ctx, "Settings", cursor, footer)?
+            }
+            Scene::Controls { cursor, waiting, .. } => {
+                let footer = if waiting {
+                    "Press the new key"
+                } else {
+                    "Enter to change a key, Escape to go back"
+                };
+                self.draw_menu(ctx, "Controls", cursor, footer)?
+            }
+            Scene::Stats {
+                cursor,
+                ref message,
+            } => {
+                let footer = match *message {
+                    Some(ref message) => message.as_str(),
+                    None => "Up/Down to read, Enter to select, Escape to go back",
+                };
+                self.draw_menu(ctx, "Statistics", cursor, footer)?
+            }
+            Scene::Pause { cursor } => {
+                let footer = "Up/Down to choose, Enter to select, Escape to resume";
+                self.draw_menu(ctx, "Paused", cursor, footer)?
+            }
+            Scene::Intro { .. } => self.draw_intro(ctx)?,
+            Scene::Playing => {
+                // Blink while recovering from a hit.
+                let blink = self.run.health.invulnerable() && (self.frames / 5).is_multiple_of(2);
+                if !blink {
+                    self.player.draw(&mut self.assets, ctx, (0, 0))?;
+                }
+
+                match self.visibility {
+                    Visibility::Shown => {
+                        for e in &mut self.enemies {
+                            e.draw(&mut self.assets, ctx, (0, 0))?;
+                        }
+                    }
+                    Visibility::AudioOnly => (),
+                    Visibility::RevealHits => {
+                        for &(pos, tag, _) in &self.run.reveals {
+                            graphics::draw(ctx, self.assets.actor_image(tag), pos, 0.0)?;
+                        }
+                    }
+                }
+                if self.visibility == Visibility::Shown && self.profile().visual_hints {
+                    if let Some(t) = self.target_index() {
+                        let bounds = self.enemies[t].bounds().rect();
+                        graphics::set_color(ctx, graphics::Color::new(1.0, 0.8, 0.2, 1.0))?;
+                        graphics::rectangle(ctx, graphics::DrawMode::Line(2.0), bounds)?;
+                        graphics::set_color(ctx, graphics::WHITE)?;
+                    }
+                }
+
+                if self.visibility == Visibility::Shown {
+                    for bomb in &self.run.bombs {
+                        let mode = graphics::DrawMode::Fill;
+                        graphics::circle(ctx, mode, bomb.pos, BOMB_RADIUS, 0.5)?;
+                    }
+                }
+
+                for shot in &self.run.shots {
+                    draw_shot(shot, &mut self.assets, ctx)?;
+                }
+                self.draw_hud(ctx)?;
+            }
+            Scene::Complete {
+                ref result,
+                new_best,
+            } => self.draw_complete(ctx, result, new_best)?,
+            Scene::GameOver { ref result } => self.draw_game_over(ctx, result)?,
+        }
+
+        if self.show_fps {
+            let fps = format!("FPS {:.0}", timer::get_fps(ctx));
+            draw_text(ctx, &self.small_font, &fps, 10.0, SCREEN_HEIGHT as f32 - 40.0)?;
+        }
+        self.frames += 1;
+
+        graphics::present(ctx);
+
+        Ok(())
+    }
+
+    // Handle key events.  These just map keyboard events
+    // and alter our input state appropriately.
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
+        if let Scene::Controls {
+            cursor,
+            waiting: true,
+            ..
+        } = self.scene
+        {
+            self.rebind(ctx, cursor, keycode);
+            return;
+        }
+        if self.bindings.action(keycode) == Some(Action::Screenshot) {
+            let img = graphics::screenshot(ctx).expect("Could not take screenshot");
+            img.encode(ctx, graphics::ImageFormat::Png, "/screenshot.png")
+                .expect("Could not save screenshot");
+            return;
+        }
+
+        match self.scene {
+            Scene::Intro { .. } | Scene::Playing => self.play_key_down(keycode),
+            _ => {
+                if let Some(input) = MenuInput::from_key(keycode) {
+                    self.menu_input(ctx, input);
+                }
+            }
+        }
+    }
+
+    fn controller_button_down_event(&mut self, ctx: &mut Context, btn: Button, _instance_id: i32) {
+        match self.scene {
+            Scene::Intro { .. } | Scene::Playing => {
+                if let Some(action) = gamepad::button_action(btn) {
+                    self.action_down(action);
+                }
+            }
+            _ => {
+                if let Some(input) = MenuInput::from_button(btn) {
+                    self.menu_input(ctx, input);
+                }
+            }
+        }
+    }
+
+    fn controller_button_up_event(&mut self, _ctx: &mut Context, btn: Button, _instance_id: i32) {
+        if let Some(action) = gamepad::button_action(btn) {
+            self.action_up(action);
+        }
+    }
+
+    fn controller_axis_event(
+        &mut self,
+        ctx: &mut Context,
+        axis: Axis,
+        value: i16,
+        _instance_id: i32,
+    ) {
+        match axis {
+            Axis::LeftX => self.input.stick = gamepad::axis_value(value),
+            Axis::LeftY => {
+                if let Some(input) = self.gamepad.stick_menu(value) {
+                    match self.scene {
+                        Scene::Intro { .. } | Scene::Playing => (),
+                        _ => self.menu_input(ctx, input),
+                    }
+                }
+            }
+            Axis::TriggerLeft => self.input.triggers[0] = gamepad::trigger_pulled(value),
+            Axis::TriggerRight => self.input.triggers[1] = gamepad::trigger_pulled(value),
+            _ => (),
+        }
+    }
+
+    fn text_input_event(&mut self, _ctx: &mut Context, text: String) {
+        if let Scene::Playing = self.scene {
+            if let Some(ref mut dictation) = self.run.dictation {
+                dictation.answer.extend(text.chars().filter(|c| !c.is_control()));
+            }
+        }
+    }
+
+    fn mouse_motion_event(
+        &mut self,
+        _ctx: &mut Context,
+        _state: MouseState,
+        x: i32,
+        y: i32,
+        _xrel: i32,
+        _yrel: i32,
+    ) {
+        self.input.aim = Some(Point2::new(x as f32, y as f32));
+    }
+
+    fn mouse_button_down_event(
+        &mut self,
+        _ctx: &mut Context,
+        button: MouseButton,
+        _x: i32,
+        _y: i32,
+    ) {
+        if button == MouseButton::Left {
+            self.input.click = true;
+        }
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, _x: i32, _y: i32) {
+        if button == MouseButton::Left {
+            self.input.click = false;
+        }
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: i32, y: i32) {
+        if let Scene::Playing = self.scene {
+            self.retune_player(100.0 * y as f32);
+        }
+    }
+
+    fn key_up_event(&mut self, _ctx: &mut Context, keycode: Keycode, _keymod: Mod, _repeat: bool) {
+        if let Some(action) = self.bindings.action(keycode) {
+            self.action_up(action);
+        }
+    }
+}
+
+impl MainState {
+    fn action_down(&mut self, action: Action) {
+        match action {
+            Action::Left => {
+                self.input.left = true;
+            }
+            Action::Right => {
+                self.input.right = true;
+            }
+            Action::Jump => {
+                self.input.jump = true;
+            }
+            Action::Shoot => {
+                self.input.shoot = true;
+            }
+            Action::Pause => self.pause(),
+            Action::Hud => self.speak_hud(),
+            Action::PitchUp => self.retune_player(100.0),
+            Action::PitchDown => self.retune_player(-100.0),
+            Action::FineUp => self.retune_player(modes::FINE_STEP_CENTS),
+            Action::FineDown => self.retune_player(-modes::FINE_STEP_CENTS),
+            Action::Screenshot => (),
+        }
+    }
+
+    fn action_up(&mut self, action: Action) {
+        match action {
+            Action::Left => self.input.left = false,
+            Action::Right => self.input.right = false,
+            Action::Shoot => self.input.shoot = false,
+            Action::Jump => {
+                self.input.jump = false;
+            }
+            _ => (), // Do nothing
+        }
+    }
+
+    fn play_key_down(&mut self, keycode: Keycode) {
+        if let Some(action) = self.bindings.action(keycode) {
+            self.action_down(action);
+            return;
+        }
+
+        // Answers, which can't be rebound.
+        match keycode {
+            Keycode::Num1 => self.answer_interval(0),
+            Keycode::Num2 => self.answer_interval(1),
+            Keycode::Num3 => self.answer_interval(2),
+            Keycode::Num4 => self.answer_interval(3),
+            Keycode::Num5 => self.answer_interval(4),
+            Keycode::Num6 => self.answer_interval(5),
+            Keycode::Num7 => self.answer_interval(6),
+            Keycode::Num8 => self.answer_interval(7),
+            Keycode::Num9 => self.answer_interval(8),
+            Keycode::Return => self.submit_dictation(),
+            Keycode::Backspace => {
+                if let Some(ref mut dictation) = self.run.dictation {
+                    dictation.answer.pop();
+                }
+            }
+            Keycode::Tab => {
+                if let Some(ref mut dictation) = self.run.dictation {
+                    dictation.beat = 0.0;
+                }
+            }
+            _ => (), // Do nothing
+        }
+    }
+
+    fn retune_player(&mut self, cents: f32) {
+        if self.mode == GameMode::PitchMatch {
+            self.player.tone = modes::retune(self.player.tone, cents);
+            self.speech.say(&modes::describe(self.player.tone));
+        }
+    }
+
+    fn pause(&mut self) {
+        self.mute();
+        self.input = InputState::default();
+        self.scene = Scene::Pause { cursor: 0 };
+        self.speech.say("Paused. Resume");
+    }
+
+    /// Quiets everything that sounds during play. The next update of the
+    /// level sets it all going again.
+    fn mute(&mut self) {
+        let mut ids: Vec<u64> = self.player.sound_id.into_iter().collect();
+        for e in &self.enemies {
+            ids.extend(e.sound_id);
+            ids.extend(e.chord.iter().map(|&(id, _)| id));
+        }
+        if let Some(ref dictation) = self.run.dictation {
+            ids.extend(dictation.sounds.iter().cloned());
+        }
+        for id in ids {
+            self.swave
+                .send(WaveCommand::Update(
+                    id,
+                    WaveUpdate {
+                        freq: 0.0,
+                        amp: (0.0, 0.0),
+                    },
+                ))
+                .unwrap();
+        }
+        for bomb in &self.run.bombs {
+            bomb.silence(&self.swave);
+        }
+        for shot in &self.run.shots {
+            shot.silence(&self.swave);
+        }
+        self.cues.silence(&self.swave);
+    }
+
+    fn settings(&self) -> &'static [Setting] {
+        match self.scene {
+            Scene::Settings { paused, .. } => menu::settings(paused),
+            _ => menu::settings(false),
+        }
+    }
+
+    fn setting_line(&self, setting: Setting) -> String {
+        match setting {
+            Setting::Mode => format!("Mode: {}", self.mode.name()),
+            Setting::Difficulty => format!("Difficulty: {}", self.profile().name),
+            Setting::Adaptive => {
+                format!("Adaptive difficulty: {}", if self.adaptive { "on" } else { "off" })
+            }
+            Setting::Enemies => format!("Enemies: {}", self.visibility.name()),
+            Setting::Speech => {
+                format!("Speech: {}", if self.speech.enabled { "on" } else { "off" })
+            }
+            Setting::Fps => format!("FPS counter: {}", if self.show_fps { "on" } else { "off" }),
+            Setting::Volume => format!("Volume: {:.0}%", self.volume * 100.0),
+            Setting::Device => format!(
+                "Audio device: {} (from next start)",
+                self.device.as_ref().map_or("default", |d| d.as_str())
+            ),
+            Setting::Controls => "Controls".to_owned(),
+            Setting::Back => "Back".to_owned(),
+        }
+    }
+
+    /// Moves a setting one step, back if `step` is negative. Switches are
+    /// toggled either way.
+    fn change_setting(&mut self, setting: Setting, step: i32) {
+        match setting {
+            Setting::Mode if step < 0 => self.mode = self.mode.prev(),
+            Setting::Mode => self.mode = self.mode.next(),
+            Setting::Difficulty => {
+                let count = self.profiles.len() as i32;
+                self.profile = (self.profile as i32 + step).rem_euclid(count) as usize;
+            }
+            Setting::Enemies if step < 0 => self.visibility = self.visibility.prev(),
+            Setting::Enemies => self.visibility = self.visibility.next(),
+            Setting::Speech => {
+                if self.speech.enabled {
+                    self.speech.say("Speech off");
+                }
+                self.speech.enabled = !self.speech.enabled;
+            }
+            Setting::Fps => self.show_fps = !self.show_fps,
+            Setting::Adaptive => self.adaptive = !self.adaptive,
+            Setting::Volume => {
+                self.volume = (self.volume + VOLUME_STEP * step as f32).clamp(0.0, 1.0);
+                self.swave.send(WaveCommand::Volume(self.volume)).unwrap();
+            }
+            Setting::Device => {
+                // The default comes first, then each device by name.
+                let names = output_device_names();
+                let current = match self.device {
+                    Some(ref d) => names.iter().position(|n| n == d).map_or(0, |i| i + 1),
+                    None => 0,
+                };
+                let next = (current as i32 + step).clamp(0, names.len() as i32) as usize;
+                self.device = next.checked_sub(1).and_then(|i| names.get(i)).cloned();
+            }
+            Setting::Controls | Setting::Back => return,
+        }
+        self.store();
+        let line = self.setting_line(setting);
+        self.speech.say(&line);
+    }
+
+    /// Copies settings and progress into the save, to be written out on
+    /// the next update.
+    fn store(&mut self) {
+        {
+            let prefs = &mut self.save.preferences;
+            prefs.mode = self.mode;
+            prefs.difficulty = self.profiles[self.profile].name.to_owned();
+            prefs.visibility = self.visibility;
+            prefs.speech = Some(self.speech.enabled);
+            prefs.show_fps = self.show_fps;
+            prefs.adaptive = self.adaptive;
+            prefs.volume = self.volume;
+            prefs.device = self.device.clone();
+        }
+        self.save.record(&self.campaign);
+        self.unsaved = true;
+    }
+
+    /// The lines of the menu being shown, one per item the cursor can rest
+    /// on.
+    fn menu_lines(&self) -> Vec<String> {
+        match self.scene {
+            Scene::Title { .. } => TITLE_ITEMS.iter().map(|i| i.name().to_owned()).collect(),
+            Scene::LevelSelect { .. } => {
+                let mut lines: Vec<String> = (0..self.campaign.entries.len())
+                    .map(|i| self.entry_line(i))
+                    .collect();
+                lines.push(PRACTICE_LINE.to_owned());
+                lines
+            }
+            Scene::Settings { .. } => {
+                self.settings().iter().map(|&s| self.setting_line(s)).collect()
+            }
+            Scene::Controls {
+                cursor, waiting, ..
+            } => {
+                let mut lines: Vec<String> = ACTIONS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &a)| match (waiting, i == cursor) {
+                        (true, true) => format!("{}: press a key", a.name()),
+                        _ => format!("{}: {}", a.name(), self.bindings.key(a).name()),
+                    })
+                    .collect();
+                lines.push("Reset to defaults".to_owned());
+                lines.push("Back".to_owned());
+                lines
+            }
+            Scene::Stats { .. } => {
+                let mut lines = self.stats_lines();
+                lines.extend(STATS_ITEMS.iter().map(|i| i.name().to_owned()));
+                lines
+            }
+            Scene::Pause { .. } => PAUSE_ITEMS.iter().map(|i| i.name().to_owned()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn show_title(&mut self, cursor: usize) {
+        self.scene = Scene::Title { cursor };
+        let line = format!("Tonal. {}", TITLE_ITEMS[cursor].name());
+        self.speech.say(&line);
+    }
+
+    /// The figures on the statistics screen, one line each so that they
+    /// are read out in turn as the cursor moves.
+    fn stats_lines(&self) -> Vec<String> {
+        let stats = &self.save.stats;
+        let total = stats.total();
+        if total.heard == 0 {
+            return vec!["Play a level to see your statistics".to_owned()];
+        }
+
+        let percent = |t: &Tally| format!("{:.0}%", t.hit_rate() * 100.0);
+        let seconds = |s: Option<f32>| s.map_or("-".to_owned(), |s| format!("{:.1}s", s));
+        let ranked = |weakest: Vec<(&str, &Tally)>| {
+            if weakest.is_empty() {
+                "not enough played yet".to_owned()
+            } else {
+                let names: Vec<String> =
+                    weakest.iter().map(|&(n, t)| format!("{} {}", n, percent(t))).collect();
+                names.join(", ")
+            }
+        };
+
+        let weakest_notes = stats.weakest_notes(WEAKEST_SHOWN);
+        let mut lines = vec![
+            format!("Notes heard: {}, hit {}", total.heard, percent(&total)),
+            format!(
+                "Average time to find {}, to shoot {}",
+                seconds(total.mean_find_time()),
+                seconds(total.mean_hit_time())
+            ),
+            format!("Weakest notes: {}", ranked(weakest_notes.clone())),
+            format!(
+                "Weakest intervals: {}",
+                ranked(stats.weakest_intervals(WEAKEST_SHOWN))
+            ),
+        ];
+        let confusions: Vec<String> = weakest_notes
+            .iter()
+            .filter_map(|&(n, t)| t.confused_with().map(|c| format!("{} for {}", n, c)))
+            .collect();
+        if !confusions.is_empty() {
+            lines.push(format!("Often mistaken: {}", confusions.join(", ")));
+        }
+        if let Some((first, last)) = stats.trend() {
+            lines.push(format!(
+                "Hit rate over your first levels {:.0}%, latest {:.0}%",
+                first * 100.0,
+                last * 100.0
+            ));
+        }
+        lines.push(format!("Levels played: {}", stats.sessions.len()));
+        lines
+    }
+
+    fn show_stats(&mut self) {
+        self.scene = Scene::Stats {
+            cursor: 0,
+            message: None,
+        };
+        let line = format!("Statistics. {}", self.menu_lines()[0]);
+        self.speech.say(&line);
+    }
+
+    /// Writes the statistics to the user data directory, for a teacher.
+    fn export_stats(&mut self, ctx: &mut Context, item: StatsItem) {
+        let stats = &self.save.stats;
+        let written = match item {
+            StatsItem::ExportCsv => write_file(ctx, STATS_CSV_PATH, &stats.to_csv())
+                .and_then(|_| write_file(ctx, SESSIONS_CSV_PATH, &stats.sessions_csv()))
+                .map(|_| "stats.csv and sessions.csv"),
+            StatsItem::ExportJson => stats
+                .to_json()
+                .map_err(GameError::UnknownError)
+                .and_then(|json| write_file(ctx, STATS_JSON_PATH, &json))
+                .map(|_| "stats.json"),
+            StatsItem::Back => return,
+        };
+        let message = match written {
+            Ok(files) => format!("Saved {} in the game's data folder", files),
+            Err(e) => format!("Could not export: {}", e),
+        };
+        self.speech.say(&message);
+        if let Scene::Stats {
+            message: ref mut shown,
+            ..
+        } = self.scene
+        {
+            *shown = Some(message);
+        }
+    }
+
+    fn show_settings(&mut self, paused: bool) {
+        self.scene = Scene::Settings { cursor: 0, paused };
+        let line = format!("Settings. {}", self.setting_line(self.settings()[0]));
+        self.speech.say(&line);
+    }
+
+    /// Binds the action highlighted on the controls screen to `keycode`,
+    /// and saves the bindings.
+    fn rebind(&mut self, ctx: &mut Context, cursor: usize, keycode: Keycode) {
+        let action = ACTIONS[cursor];
+        self.bindings.bind(action, keycode);
+        self.scene.stop_waiting();
+        self.save_bindings(ctx);
+        let line = format!("{}: {}", action.name(), keycode.name());
+        self.speech.say(&line);
+    }
+
+    fn save_bindings(&mut self, ctx: &mut Context) {
+        if let Err(e) = self.bindings.save(ctx, BINDINGS_PATH) {
+            self.speech.say(&format!("Could not save the controls. {}", e));
+        }
+    }
+
+    fn menu_input(&mut self, ctx: &mut Context, input: MenuInput) {
+        // A gamepad can't bind keys, only give up waiting for one.
+        if let Scene::Controls { waiting: true, .. } = self.scene {
+            if input == MenuInput::Back {
+                self.scene.stop_waiting();
+            }
+            return;
+        }
+
+        if let Some(cursor) = self.scene.cursor() {
+            let lines = self.menu_lines();
+            let moved = menu::move_cursor(cursor, lines.len(), input);
+            if moved != cursor {
+                self.scene.set_cursor(moved);
+                self.speech.say(&lines[moved]);
+                return;
+            }
+        }
+
+        match self.scene {
+            Scene::Title { cursor } => match (input, TITLE_ITEMS[cursor]) {
+                (MenuInput::Select, TitleItem::Play) => self.leave_level(),
+                (MenuInput::Select, TitleItem::Settings) => self.show_settings(false),
+                (MenuInput::Select, TitleItem::Stats) => self.show_stats(),
+                (MenuInput::Select, TitleItem::Quit) | (MenuInput::Back, _) => ctx.quit().unwrap(),
+                _ => (),
+            },
+            Scene::LevelSelect { cursor } => match input {
+                MenuInput::Select if cursor == self.campaign.entries.len() => self.start_practice(),
+                MenuInput::Select if self.campaign.is_unlocked(cursor) => self.start_level(cursor),
+                MenuInput::Select => self.speech.say("Locked"),
+                MenuInput::Back => self.show_title(0),
+                _ => (),
+            },
+            Scene::Settings { cursor, paused } => match (input, self.settings()[cursor]) {
+                (MenuInput::Select, Setting::Controls) => {
+                    self.scene = Scene::Controls {
+                        cursor: 0,
+                        paused,
+                        waiting: false,
+                    };
+                    let line = format!("Controls. {}", self.menu_lines()[0]);
+                    self.speech.say(&line);
+                }
+                (MenuInput::Select, Setting::Back) | (MenuInput::Back, _) => {
+                    if paused {
+                        self.scene = Scene::Pause { cursor: 2 };
+                        self.speech.say(PauseItem::Settings.name());
+                    } else {
+                        self.show_title(1);
+                    }
+                }
+                (MenuInput::Left, setting) => self.change_setting(setting, -1),
+                (MenuInput::Select, setting) | (MenuInput::Right, setting) => {
+                    self.change_setting(setting, 1)
+                }
+                _ => (),
+            },
+            Scene::Controls { cursor, paused, .. } => match input {
+                MenuInput::Select if cursor < ACTIONS.len() => {
+                    self.scene = Scene::Controls {
+                        cursor,
+                        paused,
+                        waiting: true,
+                    };
+                    let line = format!("Press a key for {}", ACTIONS[cursor].name());
+                    self.speech.say(&line);
+                }
+                MenuInput::Select if cursor == ACTIONS.len() => {
+                    self.bindings = Bindings::default();
+                    self.save_bindings(ctx);
+                    self.speech.say("Controls reset");
+                }
+                MenuInput::Select | MenuInput::Back => {
+                    let settings = menu::settings(paused);
+                    let cursor = settings.iter().position(|&s| s == Setting::Controls).unwrap();
+                    self.scene = Scene::Settings { cursor, paused };
+                    self.speech.say(&self.setting_line(Setting::Controls));
+                }
+                _ => (),
+            },
+            Scene::Stats { cursor, .. } => {
+                let figures = self.menu_lines().len() - STATS_ITEMS.len();
+                let item = cursor.checked_sub(figures).map(|i| STATS_ITEMS[i]);
+                match (input, item) {
+                    (MenuInput::Select, Some(StatsItem::Back)) | (MenuInput::Back, _) => {
+                        self.show_title(2)
+                    }
+                    (MenuInput::Select, Some(item)) => self.export_stats(ctx, item),
+                    _ => (),
+                }
+            }
+            Scene::Pause { cursor } => match (input, PAUSE_ITEMS[cursor]) {
+                (MenuInput::Select, PauseItem::Resume) | (MenuInput::Back, _) => {
+                    self.scene = Scene::Playing
+                }
+                (MenuInput::Select, PauseItem::Restart) => self.restart_level(),
+                (MenuInput::Select, PauseItem::Settings) => self.show_settings(true),
+                (MenuInput::Select, PauseItem::Quit) => self.leave_level(),
+                _ => (),
+            },
+            Scene::Complete { .. } => match input {
+                MenuInput::Select if self.run.practice.is_some() => self.start_practice(),
+                MenuInput::Select => {
+                    let next = self.run.level + 1;
+                    if next < self.campaign.entries.len() && self.campaign.is_unlocked(next) {
+                        self.start_level(next);
+                    } else {
+                        self.leave_level();
+                    }
+                }
+                MenuInput::Back => self.leave_level(),
+                _ => (),
+            },
+            Scene::GameOver { .. } => match input {
+                MenuInput::Select => self.restart_level(),
+                MenuInput::Back => self.leave_level(),
+                _ => (),
+            },
+            Scene::Intro { .. } | Scene::Playing => (),
+        }
+    }
+}
+
+fn handle_player_input(player: &mut Actor, input: &InputState, dseconds: f32) {
+    let cont = 0.0 + if input.left { -1.0 } else { 0.0 } + if input.right { 1.0 } else { 0.0 };
+    let cont = (cont + input.stick).clamp(-1.0, 1.0);
+
+    player.pos.x += cont * X_PLAYER_MAX_SPEED * dseconds;
+
+    if player.pos.y >= GROUND_Y {
+        if input.jump {
+            player.velocity.y = -JUMP_SPEED;
+        }
+    } else if player.velocity.y < 0.0 && !input.jump {
+        player.velocity.y += GRAVITY * JUMP_CUT * dseconds;
+    } else {
+        player.velocity.y += GRAVITY * dseconds;
+    }
+}
+
+/// Stops a falling player at the ground, returning whether they just
+/// landed.
+fn apply_ground(a: &mut Actor) -> bool {
+    if a.pos.y > GROUND_Y {
+        a.pos.y = GROUND_Y;
+        a.velocity.y = 0.0;
+        true
+    } else {
+        false
+    }
+}
+
+/// `time` is seconds into the level, and `on_beat` whether a shielded
+/// enemy could be hit right now; shields drop their voice between beats.
+fn update_enemy_sound(
+    player: &Actor,
+    enemy: &Actor,
+    time: f32,
+    on_beat: bool,
+    swave: &Sender<WaveCommand>,
+) {
+    // Enemies level with the player's ear are loudest, so jumping helps
+    // tell high enemies from low ones.
+    let elevation = 1.0
+        - ELEVATION_FALLOFF * (player.center().y - enemy.center().y).abs() / SCREEN_HEIGHT as f32;
+
+    let freq = match enemy.tag {
+        ActorType::Glissando => {
+            let glide = 1.0 - ((time - enemy.spawned_at) % GLIDE_TIME) / GLIDE_TIME;
+            enemy.tone * 2.0f32.powf(GLIDE_SEMITONES * glide / 12.0)
+        }
+        _ => enemy.tone,
+    };
+
+    let mut leftamp =
+        1.0 - (((player.center().x - EAR_DIST) - enemy.pos.x).abs() / SCREEN_WIDTH as f32);
+    leftamp *= leftamp;
+    let mut rightamp =
+        1.0 - ((player.center().x + EAR_DIST) - enemy.pos.x).abs() / SCREEN_WIDTH as f32;
+    rightamp *= rightamp;
+    leftamp *= elevation;
+    rightamp *= elevation;
+
+    if enemy.tag == ActorType::Shielded && !on_beat {
+        leftamp /= 2.0;
+        rightamp /= 2.0;
+    }
+
+    // let sideamp = if player.center().x < enemy.pos.x {
+    //     (amp, 0.0)
+    // } else if player.center().x > enemy.pos.x + enemy.width as f32 {
+    //     (0.0, amp)
+    // }
+    // else {
+    //     (amp, amp)
+    // };
+
+    //println!("{:?} {:?}", freq, amp);
+
+    swave
+        .send(WaveCommand::Update(
+            enemy.sound_id.unwrap(),
+            WaveUpdate {
+                freq,
+                amp: (leftamp, rightamp),
+            },
+        ))
+        .unwrap();
+    for &(sound_id, freq) in &enemy.chord {
+        swave
+            .send(WaveCommand::Update(
+                sound_id,
+                WaveUpdate {
+                    freq,
+                    amp: (leftamp, rightamp),
+                },
+            ))
+            .unwrap();
+    }
+}
+
+/// Index of the nearest enemy in the line of fire.
+fn aimed_at(ray: &Ray, enemies: &[Actor]) -> Option<usize> {
+    enemies
+        .iter()
+        .enumerate()
+        .filter_map(|(i, e)| ray.hits(&e.bounds()).map(|distance| (distance, i)))
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap())
+        .map(|(_, i)| i)
+}
+
+/// Sounds the cue for the nearest enemy in the line of fire.
+fn update_player_sound(
+    player: &Actor,
+    ray: &Ray,
+    enemies: &[Actor],
+    cue_detune: f32,
+    swave: &Sender<WaveCommand>,
+) {
+    let command = if let Some(e) = aimed_at(ray, enemies).map(|i| &enemies[i]) {
+        WaveUpdate {
+            freq: e.tone + cue_detune,
+            amp: (TARGET_AMPLITUDE, TARGET_AMPLITUDE),
+        }
+    } else {
+        WaveUpdate {
+            freq: 0.0,
+            amp: (0.0, 0.0),
+        }
+    };
+
+    swave
+        .send(WaveCommand::Update(player.sound_id.unwrap(), command))
+        .unwrap();
+}
+
+/// In pitch-matching mode the player's own tone always sounds, so it beats
+/// against an enemy's when the two are close.
+fn update_player_pitch_sound(player: &Actor, swave: &Sender<WaveCommand>) {
+    swave
+        .send(WaveCommand::Update(
+            player.sound_id.unwrap(),
+            WaveUpdate {
+                freq: player.tone,
+                amp: (TARGET_AMPLITUDE, TARGET_AMPLITUDE),
+            },
+        ))
+        .unwrap();
+}
+
+fn apply_motion(a: &mut Actor, dseconds: f32) {
+    a.pos += a.velocity.map(|i| i * dseconds);
+}
+
+fn apply_walls(a: &mut Actor, gutter: bool) {
+    let bottom = if gutter { 50.0 } else { 0.0 };
+
+    if a.pos.x + a.width as f32 > SCREEN_WIDTH as f32 {
+        a.pos.x = (SCREEN_WIDTH - a.width) as f32;
+        a.velocity.x = -a.velocity.x
+    }
+    if a.pos.x < 0.0 {
+        a.pos.x = 0.0;
+        a.velocity.x = -a.velocity.x
+    }
+    if a.pos.y + a.height as f32 > SCREEN_HEIGHT as f32 - bottom {
+        a.pos.y = (SCREEN_HEIGHT - bottom as u32 - a.height) as f32;
+        a.velocity.y = -a.velocity.y
+    }
+    if a.pos.y < 0.0 {
+        a.pos.y = 0.0;
+        a.velocity.y = -a.velocity.y
+    }
+}
+
+fn handle_shoot(state: &mut MainState, dseconds: f32) {
+    let reload_time = state.run.difficulty.reload_time;
+    state.gun.time_to_reload = f32::max(0.0, state.gun.time_to_reload - dseconds);
+
+    if state.input.shooting() && state.gun.time_to_reload <= 0.0 {
+        if state.audio_cues() {
+            state.cues.shot();
+        }
+        // A shot sings the player's tone when matching pitch, and otherwise
+        // the note it is aimed at.
+        let ray = state.aim_ray();
+        let pitch = match (state.mode, aimed_at(&ray, &state.enemies)) {
+            (GameMode::PitchMatch, _) | (_, None) => state.player.tone,
+            (_, Some(i)) => state.enemies[i].tone,
+        };
+        let shot = Shot::new(ray.origin, ray.direction, pitch, &state.swave);
+        state.run.shots.push(shot);
+        state.gun.time_to_reload = reload_time;
+    }
 }
 
 // Now our main function, which does three things:
@@ -0,0 +1,176 @@
+//! Moving around the menu screens, which work the same from the keyboard
+//! and a gamepad.
+
+use ggez::event::{Button, Keycode};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MenuInput {
+    Up,
+    Down,
+    /// Left and right change the highlighted setting.
+    Left,
+    Right,
+    Select,
+    Back,
+}
+
+impl MenuInput {
+    pub fn from_key(keycode: Keycode) -> Option<MenuInput> {
+        match keycode {
+            Keycode::Up => Some(MenuInput::Up),
+            Keycode::Down => Some(MenuInput::Down),
+            Keycode::Left => Some(MenuInput::Left),
+            Keycode::Right => Some(MenuInput::Right),
+            Keycode::Return | Keycode::Space => Some(MenuInput::Select),
+            Keycode::Escape => Some(MenuInput::Back),
+            _ => None,
+        }
+    }
+
+    pub fn from_button(button: Button) -> Option<MenuInput> {
+        match button {
+            Button::DPadUp => Some(MenuInput::Up),
+            Button::DPadDown => Some(MenuInput::Down),
+            Button::DPadLeft => Some(MenuInput::Left),
+            Button::DPadRight => Some(MenuInput::Right),
+            Button::A | Button::Start => Some(MenuInput::Select),
+            Button::B | Button::Back => Some(MenuInput::Back),
+            _ => None,
+        }
+    }
+}
+
+/// Where the cursor over `len` items ends up after `input`. It stops at
+/// the first and last items rather than wrapping.
+pub fn move_cursor(cursor: usize, len: usize, input: MenuInput) -> usize {
+    match input {
+        MenuInput::Up if cursor > 0 => cursor - 1,
+        MenuInput::Down if cursor + 1 < len => cursor + 1,
+        _ => cursor,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TitleItem {
+    Play,
+    Settings,
+    Stats,
+    Quit,
+}
+
+pub const TITLE_ITEMS: [TitleItem; 4] = [
+    TitleItem::Play,
+    TitleItem::Settings,
+    TitleItem::Stats,
+    TitleItem::Quit,
+];
+
+impl TitleItem {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TitleItem::Play => "Play",
+            TitleItem::Settings => "Settings",
+            TitleItem::Stats => "Statistics",
+            TitleItem::Quit => "Quit",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PauseItem {
+    Resume,
+    Restart,
+    Settings,
+    Quit,
+}
+
+pub const PAUSE_ITEMS: [PauseItem; 4] = [
+    PauseItem::Resume,
+    PauseItem::Restart,
+    PauseItem::Settings,
+    PauseItem::Quit,
+];
+
+impl PauseItem {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PauseItem::Resume => "Resume",
+            PauseItem::Restart => "Restart level",
+            PauseItem::Settings => "Settings",
+            PauseItem::Quit => "Quit to level select",
+        }
+    }
+}
+
+/// The choices below the figures on the statistics screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsItem {
+    ExportCsv,
+    ExportJson,
+    Back,
+}
+
+pub const STATS_ITEMS: [StatsItem; 3] = [
+    StatsItem::ExportCsv,
+    StatsItem::ExportJson,
+    StatsItem::Back,
+];
+
+impl StatsItem {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            StatsItem::ExportCsv => "Export as CSV",
+            StatsItem::ExportJson => "Export as JSON",
+            StatsItem::Back => "Back",
+        }
+    }
+}
+
+/// A line on the settings screen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Setting {
+    Mode,
+    Difficulty,
+    Enemies,
+    Speech,
+    Fps,
+    Adaptive,
+    Volume,
+    Device,
+    Controls,
+    Back,
+}
+
+pub const SETTINGS: [Setting; 10] = [
+    Setting::Mode,
+    Setting::Difficulty,
+    Setting::Adaptive,
+    Setting::Enemies,
+    Setting::Speech,
+    Setting::Fps,
+    Setting::Volume,
+    Setting::Device,
+    Setting::Controls,
+    Setting::Back,
+];
+
+/// The settings that can be changed in the middle of a level; the mode and
+/// difficulty, adaptive or not, are fixed when it starts, and the audio
+/// device when the game does.
+pub const PAUSED_SETTINGS: [Setting; 6] = [
+    Setting::Enemies,
+    Setting::Speech,
+    Setting::Fps,
+    Setting::Volume,
+    Setting::Controls,
+    Setting::Back,
+];
+
+/// The settings screen's lines, when opened from the pause menu or not.
+pub fn settings(paused: bool) -> &'static [Setting] {
+    if paused {
+        &PAUSED_SETTINGS
+    } else {
+        &SETTINGS
+    }
+}
@@ -0,0 +1,415 @@
+//! Standard MIDI File reader, used to build level note lists from files
+//! authored in a DAW.
+
+use level::{Melody, Note};
+use waves::notes;
+
+const DEFAULT_TEMPO: u32 = 500_000;
+
+#[derive(Debug, Clone)]
+enum EventKind {
+    NoteOn { channel: u8, key: u8 },
+    NoteOff { channel: u8, key: u8 },
+    Tempo(u32),
+    TrackName(String),
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Event {
+    tick: u64,
+    kind: EventKind,
+}
+
+pub struct MidiFile {
+    ticks_per_beat: u16,
+    tracks: Vec<Vec<Event>>,
+}
+
+/// Which part of a MIDI file should become the level's notes. `None` picks
+/// the first track, or the lowest channel, that contains any notes.
+#[derive(Debug, Clone, Default)]
+pub struct MidiOptions {
+    pub track: Option<usize>,
+    pub channel: Option<u8>,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn eof(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.data.len() {
+            return Err(format!("unexpected end of data at byte {}", self.data.len()));
+        }
+        let b = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(b)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok((b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32)
+    }
+
+    /// Variable-length quantity, at most four bytes.
+    fn vlq(&mut self) -> Result<u32, String> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let b = self.u8()?;
+            value = (value << 7) | (b & 0x7f) as u32;
+            if b & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(format!("variable-length number too long at byte {}", self.pos))
+    }
+}
+
+fn read_track(data: &[u8]) -> Result<Vec<Event>, String> {
+    let mut r = Reader { data, pos: 0 };
+    let mut events = Vec::new();
+    let mut tick = 0u64;
+    let mut running_status: Option<u8> = None;
+
+    while !r.eof() {
+        tick += r.vlq()? as u64;
+
+        let mut status = r.u8()?;
+        let first_data = if status < 0x80 {
+            let data = status;
+            status = running_status.ok_or("data byte without a running status")?;
+            Some(data)
+        } else {
+            None
+        };
+
+        let kind = match status {
+            0xff => {
+                let meta = r.u8()?;
+                let len = r.vlq()? as usize;
+                let body = r.bytes(len)?;
+                match meta {
+                    0x03 => EventKind::TrackName(String::from_utf8_lossy(body).trim().to_owned()),
+                    0x51 if len == 3 => EventKind::Tempo(
+                        (body[0] as u32) << 16 | (body[1] as u32) << 8 | body[2] as u32,
+                    ),
+                    0x2f => break,
+                    _ => EventKind::Other,
+                }
+            }
+            0xf0 | 0xf7 => {
+                let len = r.vlq()? as usize;
+                r.bytes(len)?;
+                EventKind::Other
+            }
+            0x80..=0xef => {
+                running_status = Some(status);
+                let a = match first_data {
+                    Some(d) => d,
+                    None => r.u8()?,
+                };
+                let channel = status & 0x0f;
+                match status & 0xf0 {
+                    0xc0 | 0xd0 => EventKind::Other,
+                    0x80 => {
+                        r.u8()?;
+                        EventKind::NoteOff { channel, key: a }
+                    }
+                    0x90 => {
+                        if r.u8()? == 0 {
+                            EventKind::NoteOff { channel, key: a }
+                        } else {
+                            EventKind::NoteOn { channel, key: a }
+                        }
+                    }
+                    _ => {
+                        r.u8()?;
+                        EventKind::Other
+                    }
+                }
+            }
+            s => return Err(format!("unsupported status byte {:#04x}", s)),
+        };
+
+        events.push(Event { tick, kind });
+    }
+
+    Ok(events)
+}
+
+impl MidiFile {
+    pub fn parse(data: &[u8]) -> Result<MidiFile, String> {
+        let mut r = Reader { data, pos: 0 };
+        if r.bytes(4).ok() != Some(&b"MThd"[..]) {
+            return Err("not a Standard MIDI File (missing MThd header)".to_owned());
+        }
+        let header_len = r.u32()? as usize;
+        let header = r.bytes(header_len)?;
+        if header_len < 6 {
+            return Err("MIDI header is too short".to_owned());
+        }
+        let format = (header[0] as u16) << 8 | header[1] as u16;
+        let division = (header[4] as u16) << 8 | header[5] as u16;
+        if format > 1 {
+            return Err(format!("MIDI format {} is not supported (use format 0 or 1)", format));
+        }
+        if division & 0x8000 != 0 {
+            return Err("SMPTE time division is not supported".to_owned());
+        }
+        if division == 0 {
+            return Err("MIDI file has zero ticks per beat".to_owned());
+        }
+
+        let mut tracks = Vec::new();
+        while !r.eof() {
+            let id = r.bytes(4)?;
+            let len = r.u32()? as usize;
+            let body = r.bytes(len)?;
+            if id == b"MTrk" {
+                let events = read_track(body)
+                    .map_err(|e| format!("track {}: {}", tracks.len(), e))?;
+                tracks.push(events);
+            }
+        }
+
+        Ok(MidiFile {
+            ticks_per_beat: division,
+            tracks,
+        })
+    }
+
+    fn has_notes(&self, track: usize, channel: Option<u8>) -> bool {
+        self.tracks[track].iter().any(|e| match e.kind {
+            EventKind::NoteOn { channel: c, .. } => channel.is_none_or(|ch| ch == c),
+            _ => false,
+        })
+    }
+
+    /// Tempo changes across every track, as `(tick, microseconds per beat)`.
+    fn tempo_map(&self) -> Vec<(u64, u32)> {
+        let mut map: Vec<(u64, u32)> = self
+            .tracks
+            .iter()
+            .flat_map(|t| t.iter())
+            .filter_map(|e| match e.kind {
+                EventKind::Tempo(t) => Some((e.tick, t)),
+                _ => None,
+            })
+            .collect();
+        map.sort_by_key(|&(tick, _)| tick);
+        if map.first().is_none_or(|&(tick, _)| tick > 0) {
+            map.insert(0, (0, DEFAULT_TEMPO));
+        }
+        map
+    }
+
+    /// Converts the chosen track and channel into a melody. Timing follows
+    /// the file's tempo map, re-expressed in beats at the opening tempo.
+    pub fn melody(&self, options: &MidiOptions) -> Result<Melody, String> {
+        if let Some(c) = options.channel.filter(|&c| c > 15) {
+            return Err(format!("channel {} is out of range 0-15", c));
+        }
+        let track = match options.track {
+            Some(t) if t >= self.tracks.len() => {
+                return Err(format!(
+                    "track {} does not exist (file has {} tracks)",
+                    t,
+                    self.tracks.len()
+                ))
+            }
+            Some(t) => t,
+            None => (0..self.tracks.len())
+                .find(|&t| self.has_notes(t, options.channel))
+                .ok_or("file contains no notes on the requested channel")?,
+        };
+
+        let channel = match options.channel {
+            Some(c) => c,
+            None => self.tracks[track]
+                .iter()
+                .filter_map(|e| match e.kind {
+                    EventKind::NoteOn { channel, .. } => Some(channel),
+                    _ => None,
+                })
+                .min()
+                .ok_or(format!("track {} contains no notes", track))?,
+        };
+
+        let tempo_map = self.tempo_map();
+        let opening_tempo = tempo_map[0].1;
+        let ticks_per_beat = self.ticks_per_beat as f64;
+        let to_beats = |tick: u64| -> f32 {
+            let mut micros = 0.0;
+            let mut last = (0u64, opening_tempo);
+            for &(t, tempo) in tempo_map.iter().take_while(|&&(t, _)| t <= tick) {
+                micros += (t - last.0) as f64 / ticks_per_beat * last.1 as f64;
+                last = (t, tempo);
+            }
+            micros += (tick - last.0) as f64 / ticks_per_beat * last.1 as f64;
+            (micros / opening_tempo as f64) as f32
+        };
+
+        let mut open: Vec<(u8, u64)> = Vec::new();
+        let mut notes = Vec::new();
+        for e in &self.tracks[track] {
+            match e.kind {
+                EventKind::NoteOn { channel: c, key } if c == channel => open.push((key, e.tick)),
+                EventKind::NoteOff { channel: c, key } if c == channel => {
+                    if let Some(i) = open.iter().position(|&(k, _)| k == key) {
+                        let (_, on) = open.remove(i);
+                        let start = to_beats(on);
+                        notes.push(Note {
+                            freq: notes::midi_to_freq(key as i32),
+                            start,
+                            beats: to_beats(e.tick) - start,
+                            pattern: None,
+                        });
+                    }
+                }
+                _ => (),
+            }
+        }
+        notes.retain(|n| n.beats > 0.0);
+        notes.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+
+        if notes.is_empty() {
+            return Err(format!("track {} channel {} contains no notes", track, channel));
+        }
+
+        // In format 1 files the first track's name is the song title.
+        let title = self.tracks[0]
+            .iter()
+            .chain(self.tracks[track].iter())
+            .filter_map(|e| match e.kind {
+                EventKind::TrackName(ref name) if !name.is_empty() => Some(name.clone()),
+                _ => None,
+            })
+            .next();
+
+        Ok(Melody {
+            title,
+            tempo: Some(60_000_000.0 / opening_tempo as f32),
+            notes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A format 1 file at 96 ticks per beat with the given track bodies.
+    fn smf(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut data = b"MThd\x00\x00\x00\x06\x00\x01".to_vec();
+        data.extend_from_slice(&[0, tracks.len() as u8, 0, 96]);
+        for track in tracks {
+            data.extend_from_slice(b"MTrk");
+            data.extend_from_slice(&(track.len() as u32).to_be_bytes());
+            data.extend_from_slice(track);
+        }
+        data
+    }
+
+    /// Titled "Song", at 120 bpm until beat 2 and then twice as fast.
+    const CONDUCTOR: &[u8] = &[
+        0x00, 0xff, 0x03, 0x04, b'S', b'o', b'n', b'g',
+        0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20,
+        0x81, 0x40, 0xff, 0x51, 0x03, 0x03, 0xd0, 0x90,
+        0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    /// C4 for a beat, then D4 for two beats. Everything after the first
+    /// status byte relies on running status, with note-on at velocity 0
+    /// ending a note.
+    const TUNE: &[u8] = &[
+        0x00, 0x90, 0x3c, 0x40,
+        0x60, 0x3c, 0x00,
+        0x00, 0x3e, 0x40,
+        0x81, 0x40, 0x3e, 0x00,
+        0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    fn vlq(bytes: &[u8]) -> Result<u32, String> {
+        Reader { data: bytes, pos: 0 }.vlq()
+    }
+
+    fn midi(melody: &Melody) -> Vec<i32> {
+        melody
+            .notes
+            .iter()
+            .map(|n| notes::freq_to_midi(n.freq).round() as i32)
+            .collect()
+    }
+
+    #[test]
+    fn reads_variable_length_quantities() {
+        assert_eq!(vlq(&[0x00]), Ok(0));
+        assert_eq!(vlq(&[0x7f]), Ok(0x7f));
+        assert_eq!(vlq(&[0x81, 0x00]), Ok(0x80));
+        assert_eq!(vlq(&[0x81, 0x40]), Ok(192));
+        assert_eq!(vlq(&[0xff, 0xff, 0xff, 0x7f]), Ok(0x0fff_ffff));
+        assert!(vlq(&[0x80, 0x80, 0x80, 0x80, 0x00]).is_err());
+        assert!(vlq(&[0x81]).is_err());
+    }
+
+    #[test]
+    fn running_status_carries_over() {
+        let file = MidiFile::parse(&smf(&[TUNE])).unwrap();
+        let melody = file.melody(&MidiOptions::default()).unwrap();
+        assert_eq!(midi(&melody), vec![60, 62]);
+        assert_eq!(melody.notes[0].beats, 1.0);
+        assert_eq!(melody.notes[1].beats, 2.0);
+    }
+
+    #[test]
+    fn data_byte_without_status_is_an_error() {
+        assert!(MidiFile::parse(&smf(&[&[0x00, 0x3c, 0x40]])).is_err());
+    }
+
+    #[test]
+    fn channel_out_of_range_is_an_error() {
+        let file = MidiFile::parse(&smf(&[TUNE])).unwrap();
+        let options = MidiOptions {
+            track: None,
+            channel: Some(200),
+        };
+        let error = file.melody(&options).unwrap_err();
+        assert!(error.contains("out of range"), "{}", error);
+        let options = MidiOptions {
+            track: None,
+            channel: Some(1),
+        };
+        assert!(file.melody(&options).is_err());
+    }
+
+    #[test]
+    fn tempo_map_defaults_to_120_bpm() {
+        let file = MidiFile::parse(&smf(&[TUNE])).unwrap();
+        assert_eq!(file.tempo_map(), vec![(0, DEFAULT_TEMPO)]);
+        let file = MidiFile::parse(&smf(&[CONDUCTOR, TUNE])).unwrap();
+        assert_eq!(file.tempo_map(), vec![(0, 500_000), (192, 250_000)]);
+    }
+
+    #[test]
+    fn tempo_changes_rescale_beats() {
+        let file = MidiFile::parse(&smf(&[CONDUCTOR, TUNE])).unwrap();
+        let melody = file.melody(&MidiOptions::default()).unwrap();
+        assert_eq!(melody.title.as_deref(), Some("Song"));
+        assert_eq!(melody.tempo, Some(120.0));
+        assert_eq!(midi(&melody), vec![60, 62]);
+        // D4 is held for a beat at 120 bpm and a beat at 240 bpm.
+        assert_eq!(melody.notes[1].start, 1.0);
+        assert_eq!(melody.notes[1].beats, 1.5);
+    }
+}
@@ -0,0 +1,110 @@
+use waves::notes;
+
+/// Lowest and highest pitch the player can tune to, as MIDI notes (C3-C6).
+const LOWEST_PITCH: f32 = 48.0;
+const HIGHEST_PITCH: f32 = 84.0;
+pub const FINE_STEP_CENTS: f32 = 10.0;
+
+/// How a level is played.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameMode {
+    /// Find each enemy by ear and shoot it.
+    Classic,
+    /// Tune your own tone to the enemy's before a shot will land.
+    PitchMatch,
+    /// A chord sounds; shoot the requested chord tone.
+    Chord,
+    /// Two tones sound; name the interval between them.
+    Interval,
+    /// A phrase plays; write it down by note name.
+    Dictation,
+}
+
+impl GameMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GameMode::Classic => "Classic",
+            GameMode::PitchMatch => "Pitch match",
+            GameMode::Chord => "Chord tones",
+            GameMode::Interval => "Intervals",
+            GameMode::Dictation => "Dictation",
+        }
+    }
+
+    pub fn next(&self) -> GameMode {
+        match self {
+            GameMode::Classic => GameMode::PitchMatch,
+            GameMode::PitchMatch => GameMode::Chord,
+            GameMode::Chord => GameMode::Interval,
+            GameMode::Interval => GameMode::Dictation,
+            GameMode::Dictation => GameMode::Classic,
+        }
+    }
+
+    pub fn prev(&self) -> GameMode {
+        match self {
+            GameMode::Classic => GameMode::Dictation,
+            GameMode::PitchMatch => GameMode::Classic,
+            GameMode::Chord => GameMode::PitchMatch,
+            GameMode::Interval => GameMode::Chord,
+            GameMode::Dictation => GameMode::Interval,
+        }
+    }
+}
+
+/// How much of the enemies is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    Shown,
+    /// Enemies are never drawn; play by ear alone.
+    AudioOnly,
+    /// Enemies are hidden, but flash up briefly where they were shot.
+    RevealHits,
+}
+
+impl Visibility {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Visibility::Shown => "Shown",
+            Visibility::AudioOnly => "Audio only",
+            Visibility::RevealHits => "Audio only, reveal hits",
+        }
+    }
+
+    pub fn next(&self) -> Visibility {
+        match self {
+            Visibility::Shown => Visibility::AudioOnly,
+            Visibility::AudioOnly => Visibility::RevealHits,
+            Visibility::RevealHits => Visibility::Shown,
+        }
+    }
+
+    pub fn prev(&self) -> Visibility {
+        match self {
+            Visibility::Shown => Visibility::RevealHits,
+            Visibility::AudioOnly => Visibility::Shown,
+            Visibility::RevealHits => Visibility::AudioOnly,
+        }
+    }
+}
+
+/// Signed distance from `target` to `freq` in cents.
+pub fn cents(freq: f32, target: f32) -> f32 {
+    1200.0 * (freq / target).log2()
+}
+
+/// Moves a frequency by a number of cents, staying within the player's
+/// range.
+pub fn retune(freq: f32, cents: f32) -> f32 {
+    let midi = (notes::freq_to_midi(freq) + cents / 100.0).clamp(LOWEST_PITCH, HIGHEST_PITCH);
+    440.0 * 2.0f32.powf((midi - 69.0) / 12.0)
+}
+
+/// Note name and offset in cents, e.g. `A4 +12c`.
+pub fn describe(freq: f32) -> String {
+    let midi = notes::freq_to_midi(freq);
+    let offset = ((midi - midi.round()) * 100.0).round() as i32;
+    format!("{} {:+}c", notes::name(freq), offset)
+}
@@ -0,0 +1,312 @@
+//! MusicXML reader for uncompressed `score-partwise` files. Reads one part's
+//! pitches, durations, chords, ties, rests, `backup`/`forward` and the first
+//! `sound` tempo. Each note's `alter` already includes the key signature and
+//! any accidentals, so `key` and `accidental` elements are only display
+//! hints and are not needed to find the pitch.
+
+use std::collections::HashMap;
+
+use xml::reader::{EventReader, XmlEvent};
+
+use level::{Melody, Note};
+use waves::notes;
+
+const DEFAULT_TEMPO: f32 = 120.0;
+
+const STEPS: [(&str, i32); 7] = [
+    ("C", 0),
+    ("D", 2),
+    ("E", 4),
+    ("F", 5),
+    ("G", 7),
+    ("A", 9),
+    ("B", 11),
+];
+
+#[derive(Default)]
+struct PendingNote {
+    rest: bool,
+    chord: bool,
+    grace: bool,
+    step: Option<i32>,
+    alter: f32,
+    octave: Option<i32>,
+    duration: Option<f32>,
+    tie_start: bool,
+    tie_stop: bool,
+}
+
+/// Reads the notes of one part. `part` is the part's position in the file,
+/// starting at 0; by default the first part is used.
+pub fn melody(source: &str, part: Option<usize>) -> Result<Melody, String> {
+    let wanted = part.unwrap_or(0);
+    let mut path: Vec<String> = Vec::new();
+    let mut text = String::new();
+
+    let mut title = None;
+    let mut tempo = None;
+    let mut part_index = None;
+    let mut parts_seen = 0;
+
+    let mut divisions = 1.0;
+    let mut time = 0.0;
+    let mut last_start = 0.0;
+    let mut note: Option<PendingNote> = None;
+    let mut shift = None;
+    let mut notes: Vec<Note> = Vec::new();
+    let mut ties: HashMap<i32, usize> = HashMap::new();
+
+    for event in EventReader::from_str(source) {
+        let event = event.map_err(|e| format!("{}", e))?;
+        match event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let local = name.local_name;
+                text.clear();
+                let in_part = part_index == Some(wanted);
+                match local.as_str() {
+                    "score-timewise" => {
+                        return Err("timewise MusicXML is not supported, export partwise".to_owned())
+                    }
+                    "part" if path.last().is_some_and(|p| p == "score-partwise") => {
+                        part_index = Some(parts_seen);
+                        parts_seen += 1;
+                    }
+                    "sound" if in_part && tempo.is_none() => {
+                        tempo = attributes
+                            .iter()
+                            .find(|a| a.name.local_name == "tempo")
+                            .and_then(|a| a.value.trim().parse::<f32>().ok())
+                            .filter(|&t| t > 0.0);
+                    }
+                    "note" if in_part => note = Some(PendingNote::default()),
+                    _ => {
+                        if let Some(n) = note.as_mut() {
+                            match local.as_str() {
+                                "rest" => n.rest = true,
+                                "chord" => n.chord = true,
+                                "grace" => n.grace = true,
+                                "tie" => {
+                                    for a in &attributes {
+                                        match (a.name.local_name.as_str(), a.value.as_str()) {
+                                            ("type", "start") => n.tie_start = true,
+                                            ("type", "stop") => n.tie_stop = true,
+                                            _ => (),
+                                        }
+                                    }
+                                }
+                                _ => (),
+                            }
+                        }
+                    }
+                }
+                path.push(local);
+            }
+            XmlEvent::Characters(s) => text.push_str(&s),
+            XmlEvent::EndElement { .. } => {
+                let local = path.pop().unwrap_or_default();
+                let parent = path.last().map(|p| p.as_str()).unwrap_or("");
+                let value = text.trim().to_owned();
+                let in_part = part_index == Some(wanted);
+                text.clear();
+
+                match (local.as_str(), parent) {
+                    ("work-title", _) | ("movement-title", _) if title.is_none() => {
+                        title = Some(value)
+                    }
+                    ("part", _) if in_part => break,
+                    ("part", _) => part_index = None,
+                    ("divisions", _) if in_part => {
+                        divisions = value
+                            .parse::<f32>()
+                            .ok()
+                            .filter(|&d| d > 0.0)
+                            .ok_or(format!("'{}' is not a valid divisions value", value))?
+                    }
+                    ("duration", "backup") | ("duration", "forward") if in_part => {
+                        shift = value.parse::<f32>().ok().map(|d| d / divisions)
+                    }
+                    ("backup", _) if in_part => time -= shift.take().unwrap_or(0.0),
+                    ("forward", _) if in_part => time += shift.take().unwrap_or(0.0),
+                    ("duration", "note") => {
+                        if let Some(n) = note.as_mut() {
+                            n.duration = value.parse::<f32>().ok().map(|d| d / divisions);
+                        }
+                    }
+                    ("step", "pitch") => {
+                        if let Some(n) = note.as_mut() {
+                            n.step = STEPS.iter().find(|&&(s, _)| s == value).map(|&(_, p)| p);
+                        }
+                    }
+                    ("alter", "pitch") => {
+                        if let Some(n) = note.as_mut() {
+                            n.alter = value.parse().unwrap_or(0.0);
+                        }
+                    }
+                    ("octave", "pitch") => {
+                        if let Some(n) = note.as_mut() {
+                            n.octave = value.parse().ok();
+                        }
+                    }
+                    ("note", _) => {
+                        if let Some(n) = note.take() {
+                            if n.grace {
+                                continue;
+                            }
+                            let beats = n
+                                .duration
+                                .ok_or(format!("note at beat {} has no duration", time))?;
+                            let start = if n.chord { last_start } else { time };
+                            if !n.chord {
+                                last_start = time;
+                                time += beats;
+                            }
+                            if n.rest {
+                                continue;
+                            }
+
+                            let midi = match (n.step, n.octave) {
+                                (Some(step), Some(octave)) => {
+                                    (octave + 1) * 12 + step + n.alter.round() as i32
+                                }
+                                _ => return Err(format!("note at beat {} has no pitch", start)),
+                            };
+
+                            let tied = if n.tie_stop { ties.remove(&midi) } else { None };
+                            let idx = match tied {
+                                Some(i) => {
+                                    notes[i].beats = start + beats - notes[i].start;
+                                    i
+                                }
+                                None => {
+                                    notes.push(Note {
+                                        freq: notes::midi_to_freq(midi),
+                                        start,
+                                        beats,
+                                        pattern: None,
+                                    });
+                                    notes.len() - 1
+                                }
+                            };
+                            if n.tie_start {
+                                ties.insert(midi, idx);
+                            }
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if parts_seen <= wanted && part_index != Some(wanted) {
+        return Err(format!(
+            "part {} does not exist (file has {} parts)",
+            wanted, parts_seen
+        ));
+    }
+
+    notes.retain(|n| n.beats > 0.0);
+    notes.sort_by(|a, b| a.start.partial_cmp(&b.start).unwrap());
+    if notes.is_empty() {
+        return Err(format!("part {} contains no notes", wanted));
+    }
+    Ok(Melody {
+        title,
+        tempo: Some(tempo.unwrap_or(DEFAULT_TEMPO)),
+        notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A one-part score, two divisions to the beat, holding `measure`.
+    fn score(measure: &str) -> String {
+        format!(
+            concat!(
+                "<?xml version=\"1.0\"?><score-partwise>",
+                "<part-list><score-part id=\"P1\"/></part-list>",
+                "<part id=\"P1\"><measure number=\"1\">",
+                "<attributes><divisions>2</divisions></attributes>{}",
+                "</measure></part></score-partwise>"
+            ),
+            measure
+        )
+    }
+
+    fn note(step: &str, octave: i32, duration: u32, extra: &str) -> String {
+        format!(
+            concat!(
+                "<note>{}<pitch><step>{}</step><octave>{}</octave></pitch>",
+                "<duration>{}</duration></note>"
+            ),
+            extra, step, octave, duration
+        )
+    }
+
+    fn read(measure: &str) -> Vec<(i32, f32, f32)> {
+        melody(&score(measure), None)
+            .unwrap()
+            .notes
+            .iter()
+            .map(|n| (notes::freq_to_midi(n.freq).round() as i32, n.start, n.beats))
+            .collect()
+    }
+
+    #[test]
+    fn chord_notes_start_together() {
+        let measure = [
+            note("C", 4, 2, ""),
+            note("E", 4, 2, "<chord/>"),
+            note("G", 4, 2, "<chord/>"),
+            note("D", 4, 1, ""),
+        ]
+        .concat();
+        assert_eq!(
+            read(&measure),
+            vec![(60, 0.0, 1.0), (64, 0.0, 1.0), (67, 0.0, 1.0), (62, 1.0, 0.5)]
+        );
+    }
+
+    #[test]
+    fn backup_rewinds_for_a_second_voice() {
+        let measure = [
+            note("C", 5, 8, ""),
+            "<backup><duration>8</duration></backup>".to_owned(),
+            note("C", 4, 4, ""),
+            "<forward><duration>2</duration></forward>".to_owned(),
+            note("E", 4, 2, ""),
+        ]
+        .concat();
+        assert_eq!(
+            read(&measure),
+            vec![(72, 0.0, 4.0), (60, 0.0, 2.0), (64, 3.0, 1.0)]
+        );
+    }
+
+    #[test]
+    fn ties_join_notes_of_the_same_pitch() {
+        let measure = [
+            note("G", 4, 2, "<tie type=\"start\"/>"),
+            note("G", 4, 4, "<tie type=\"stop\"/><tie type=\"start\"/>"),
+            note("G", 4, 1, "<tie type=\"stop\"/>"),
+            note("G", 4, 1, ""),
+        ]
+        .concat();
+        assert_eq!(read(&measure), vec![(67, 0.0, 3.5), (67, 3.5, 0.5)]);
+    }
+
+    #[test]
+    fn tie_stop_without_start_is_a_new_note() {
+        let measure = [
+            note("A", 4, 2, "<tie type=\"start\"/>"),
+            note("B", 4, 2, "<tie type=\"stop\"/>"),
+        ]
+        .concat();
+        assert_eq!(read(&measure), vec![(69, 0.0, 1.0), (71, 1.0, 1.0)]);
+    }
+}
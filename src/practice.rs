@@ -0,0 +1,62 @@
+//! Practice levels that drill the notes the player misses most. A practice
+//! level keeps the rhythm and settings of a campaign level, but each note's
+//! pitch is drawn afresh, favouring pitches and octaves with a poor record.
+
+use rand::prelude::*;
+
+use level::{Level, Note};
+use stats::Stats;
+use waves::notes;
+
+/// Miss rate assumed for a note or octave not heard enough to tell.
+const UNKNOWN_MISS_RATE: f32 = 0.5;
+/// Weight every pitch has anyway, so that mastered notes still come up.
+const MIN_WEIGHT: f32 = 0.1;
+/// How much the octave's record counts next to the note's own.
+const REGISTER_WEIGHT: f32 = 0.5;
+
+/// How strongly to favour each of `pitches`, given as MIDI note numbers.
+pub fn weights(pitches: &[i32], stats: &Stats) -> Vec<f32> {
+    pitches
+        .iter()
+        .map(|&midi| {
+            let note = stats.miss_rate(&notes::midi_name(midi));
+            let register = stats.register_miss_rate(midi / 12 - 1);
+            MIN_WEIGHT
+                + note.unwrap_or(UNKNOWN_MISS_RATE)
+                + REGISTER_WEIGHT * register.unwrap_or(UNKNOWN_MISS_RATE)
+        })
+        .collect()
+}
+
+/// `base` with its notes' pitches redrawn from `pitches`, which must not be
+/// empty.
+pub fn practice_level(base: &Level, pitches: &[i32], stats: &Stats) -> Level {
+    let weights = weights(pitches, stats);
+    let total: f32 = weights.iter().sum();
+    let mut rng = thread_rng();
+    let mut draw = || {
+        let mut left = rng.gen::<f32>() * total;
+        for (&midi, &weight) in pitches.iter().zip(&weights) {
+            if left < weight {
+                return midi;
+            }
+            left -= weight;
+        }
+        pitches[pitches.len() - 1]
+    };
+
+    let drawn = base
+        .notes
+        .iter()
+        .map(|n| Note {
+            freq: notes::midi_to_freq(draw()),
+            ..n.clone()
+        })
+        .collect();
+    Level {
+        title: format!("Practice: {}", base.title),
+        notes: drawn,
+        ..base.clone()
+    }
+}
@@ -0,0 +1,240 @@
+//! What is kept between runs: the best result on each level, which levels
+//! are unlocked, the player's settings and their statistics. Key bindings
+//! live in their own file, see `bindings`.
+//!
+//! The save is written to `save.toml` in the user data directory, then
+//! copied to `save.bak.toml`, so that one of the two is whole even if the
+//! game stops mid-write. A save that can't be read is set aside as
+//! `save.corrupt.toml` and the backup used instead.
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use ggez::{Context, GameError, GameResult};
+
+use toml;
+
+use campaign::{Campaign, LevelResult};
+use modes::{GameMode, Visibility};
+use stats::Stats;
+
+const SAVE_PATH: &str = "/save.toml";
+const BACKUP_PATH: &str = "/save.bak.toml";
+const CORRUPT_PATH: &str = "/save.corrupt.toml";
+/// Bumped whenever the layout changes, with a step added to `migrate`.
+const VERSION: i64 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preferences {
+    pub mode: GameMode,
+    /// Name of the difficulty profile.
+    pub difficulty: String,
+    pub visibility: Visibility,
+    /// Unset until changed, leaving it on only when a speech program is
+    /// set up.
+    pub speech: Option<bool>,
+    pub show_fps: bool,
+    pub adaptive: bool,
+    /// Master volume, from 0 to 1.
+    pub volume: f32,
+    /// Audio output to play through, or the system default.
+    pub device: Option<String>,
+}
+
+impl Default for Preferences {
+    fn default() -> Preferences {
+        Preferences {
+            mode: GameMode::Classic,
+            difficulty: "Normal".to_owned(),
+            visibility: Visibility::Shown,
+            speech: None,
+            show_fps: false,
+            adaptive: false,
+            volume: 1.0,
+            device: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LevelRecord {
+    pub unlocked: bool,
+    pub best: Option<LevelResult>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SaveData {
+    version: i64,
+    pub preferences: Preferences,
+    /// Keyed by level file.
+    pub levels: BTreeMap<String, LevelRecord>,
+    pub stats: Stats,
+}
+
+impl Default for SaveData {
+    fn default() -> SaveData {
+        SaveData {
+            version: VERSION,
+            preferences: Preferences::default(),
+            levels: BTreeMap::new(),
+            stats: Stats::default(),
+        }
+    }
+}
+
+impl SaveData {
+    /// Loads the save, falling back to the backup and then to a fresh save.
+    pub fn load(ctx: &mut Context) -> SaveData {
+        for &path in &[SAVE_PATH, BACKUP_PATH] {
+            if !ctx.filesystem.exists(path) {
+                continue;
+            }
+            match read(ctx, path) {
+                Ok(data) => return data,
+                Err(e) => {
+                    println!("Could not read {}: {}", path, e);
+                    if path == SAVE_PATH {
+                        set_aside(ctx, path);
+                    }
+                }
+            }
+        }
+        SaveData::default()
+    }
+
+    pub fn save(&self, ctx: &mut Context) -> GameResult<()> {
+        let text = toml::Value::try_from(self)
+            .map_err(|e| GameError::UnknownError(e.to_string()))?
+            .to_string();
+        for &path in &[SAVE_PATH, BACKUP_PATH] {
+            ctx.filesystem.create(path)?.write_all(text.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Restores best results and unlocks to the campaign's levels.
+    pub fn restore(&self, campaign: &mut Campaign) {
+        for entry in &mut campaign.entries {
+            if let Some(record) = self.levels.get(&entry.file) {
+                entry.unlocked |= record.unlocked;
+                entry.best = record.best.clone();
+            }
+        }
+    }
+
+    /// Takes the campaign's best results and unlocks.
+    pub fn record(&mut self, campaign: &Campaign) {
+        for (i, entry) in campaign.entries.iter().enumerate() {
+            let record = LevelRecord {
+                unlocked: campaign.is_unlocked(i),
+                best: entry.best.clone(),
+            };
+            self.levels.insert(entry.file.clone(), record);
+        }
+    }
+}
+
+fn read(ctx: &mut Context, path: &str) -> Result<SaveData, String> {
+    let mut source = String::new();
+    ctx.filesystem
+        .open(path)
+        .and_then(|mut f| f.read_to_string(&mut source).map_err(|e| e.into()))
+        .map_err(|e| e.to_string())?;
+    parse(&source)
+}
+
+/// Reads the text of a save, bringing it up to date.
+fn parse(source: &str) -> Result<SaveData, String> {
+    let value: toml::Value = toml::from_str(source).map_err(|e| e.to_string())?;
+    let version = match value.get("version") {
+        Some(v) => v.as_integer().ok_or("version is not a number")?,
+        None => 0,
+    };
+    migrate(value, version)?
+        .try_into()
+        .map_err(|e| e.to_string())
+}
+
+/// Brings a save written by an older version of the game up to date.
+fn migrate(mut value: toml::Value, version: i64) -> Result<toml::Value, String> {
+    match version {
+        VERSION => Ok(value),
+        v if v > VERSION => Err(format!("save version {} is newer than this game", v)),
+        // Saves without a version, such as ones written by hand, have the
+        // same layout as version 1.
+        0 => {
+            let table = value.as_table_mut().ok_or("save is not a table")?;
+            table.insert("version".to_owned(), toml::Value::Integer(1));
+            migrate(value, 1)
+        }
+        v => Err(format!("unknown save version {}", v)),
+    }
+}
+
+/// Keeps a copy of an unreadable save, since the next save overwrites it.
+fn set_aside(ctx: &mut Context, path: &str) {
+    let mut bytes = Vec::new();
+    let copied = ctx
+        .filesystem
+        .open(path)
+        .and_then(|mut f| f.read_to_end(&mut bytes).map_err(|e| e.into()))
+        .and_then(|_| ctx.filesystem.create(CORRUPT_PATH))
+        .and_then(|mut f| f.write_all(&bytes).map_err(|e| e.into()));
+    if let Err(e) = copied {
+        println!("Could not keep a copy of {}: {}", path, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut data = SaveData::default();
+        data.preferences.volume = 0.25;
+        data.preferences.device = Some("Speakers".to_owned());
+        let text = toml::Value::try_from(&data).unwrap().to_string();
+        let read = parse(&text).unwrap();
+        assert_eq!(read.version, VERSION);
+        assert_eq!(read.preferences.volume, 0.25);
+        assert_eq!(read.preferences.device, Some("Speakers".to_owned()));
+    }
+
+    #[test]
+    fn unversioned_save_is_migrated() {
+        let source = concat!(
+            "[preferences]\n",
+            "volume = 0.5\n",
+            "[levels.\"/levels/1.toml\"]\n",
+            "unlocked = true\n"
+        );
+        let data = parse(source).unwrap();
+        assert_eq!(data.version, 1);
+        assert_eq!(data.preferences.volume, 0.5);
+        assert!(data.levels["/levels/1.toml"].unlocked);
+    }
+
+    #[test]
+    fn newer_save_is_rejected() {
+        let error = parse("version = 2\n[preferences]\nvolume = 0.5\n").unwrap_err();
+        assert!(error.contains("newer"), "{}", error);
+        assert!(parse("version = -1\n").is_err());
+    }
+
+    #[test]
+    fn corrupt_save_is_rejected() {
+        let sources = [
+            "version = 1\n[preferences",
+            "version = \"one\"\n",
+            "version = 1\n[preferences]\nvolume = \"loud\"\n",
+            "\u{0}\u{1}",
+        ];
+        for source in &sources {
+            assert!(parse(source).is_err(), "{:?}", source);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+const HIT_POINTS: u32 = 100;
+/// Extra points for an instant kill, shrinking to nothing at
+/// `SLOW_REACTION` seconds.
+const SPEED_BONUS: f32 = 100.0;
+const SLOW_REACTION: f32 = 5.0;
+const MISS_PENALTY: u32 = 25;
+const WRONG_TARGET_PENALTY: u32 = 50;
+/// Hits in a row needed to raise the combo multiplier by one.
+const COMBO_STEP: usize = 5;
+const MAX_COMBO: u32 = 4;
+
+/// Running tally of a level: points, accuracy, streaks and how quickly
+/// enemies were found.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Score {
+    pub points: u32,
+    pub hits: usize,
+    /// Shots that hit nothing.
+    pub misses: usize,
+    /// Shots that hit an enemy other than the one due next.
+    pub wrong_targets: usize,
+    /// Enemies that left before being shot.
+    pub escaped: usize,
+    pub streak: usize,
+    pub best_streak: usize,
+    /// Seconds from each destroyed enemy's spawn to its kill.
+    pub reaction_times: Vec<f32>,
+}
+
+impl Score {
+    /// Points multiplier earned by the current streak.
+    pub fn combo(&self) -> u32 {
+        (1 + (self.streak / COMBO_STEP) as u32).min(MAX_COMBO)
+    }
+
+    pub fn hit(&mut self, reaction_time: f32) {
+        let speed = (1.0 - reaction_time / SLOW_REACTION).max(0.0);
+        let points = HIT_POINTS + (SPEED_BONUS * speed) as u32;
+
+        self.streak += 1;
+        self.best_streak = self.best_streak.max(self.streak);
+        self.points += points * self.combo();
+        self.hits += 1;
+        self.reaction_times.push(reaction_time);
+    }
+
+    pub fn miss(&mut self) {
+        self.misses += 1;
+        self.streak = 0;
+        self.points = self.points.saturating_sub(MISS_PENALTY);
+    }
+
+    pub fn wrong_target(&mut self) {
+        self.wrong_targets += 1;
+        self.streak = 0;
+        self.points = self.points.saturating_sub(WRONG_TARGET_PENALTY);
+    }
+
+    pub fn escape(&mut self) {
+        self.escaped += 1;
+        self.streak = 0;
+    }
+
+    pub fn shots(&self) -> usize {
+        self.hits + self.misses + self.wrong_targets
+    }
+
+    /// Fraction of shots that destroyed the right enemy.
+    pub fn accuracy(&self) -> f32 {
+        if self.shots() == 0 {
+            0.0
+        } else {
+            self.hits as f32 / self.shots() as f32
+        }
+    }
+
+    pub fn mean_reaction_time(&self) -> Option<f32> {
+        if self.reaction_times.is_empty() {
+            None
+        } else {
+            Some(self.reaction_times.iter().sum::<f32>() / self.reaction_times.len() as f32)
+        }
+    }
+}
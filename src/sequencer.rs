@@ -0,0 +1,59 @@
+use std::ops::Range;
+
+use level::Note;
+
+/// Clock that walks through a level's notes at its tempo and says which
+/// ones have started.
+#[derive(Debug, Clone)]
+pub struct Sequencer {
+    bpm: f32,
+    beat: f32,
+    next: usize,
+}
+
+impl Sequencer {
+    pub fn new(bpm: f32) -> Sequencer {
+        Sequencer {
+            bpm,
+            beat: 0.0,
+            next: 0,
+        }
+    }
+
+    /// Moves the clock forward and returns the indices of the notes whose
+    /// onset has been reached. `notes` must be sorted by start.
+    pub fn advance(&mut self, seconds: f32, notes: &[Note]) -> Range<usize> {
+        self.beat += seconds * self.bpm / 60.0;
+        let first = self.next;
+        while self.next < notes.len() && notes[self.next].start <= self.beat {
+            self.next += 1;
+        }
+        first..self.next
+    }
+
+    /// Beats since the level started.
+    pub fn beat(&self) -> f32 {
+        self.beat
+    }
+
+    /// How far the clock is from the nearest beat, in beats.
+    pub fn off_beat(&self) -> f32 {
+        let fraction = self.beat.fract();
+        fraction.min(1.0 - fraction)
+    }
+
+    /// How many notes have started so far.
+    pub fn started(&self) -> usize {
+        self.next
+    }
+
+    pub fn finished(&self, notes: &[Note]) -> bool {
+        self.next >= notes.len()
+    }
+}
+
+impl Default for Sequencer {
+    fn default() -> Sequencer {
+        Sequencer::new(60.0)
+    }
+}
@@ -0,0 +1,141 @@
+//! Spoken announcements for players who can't see the screen.
+
+use std::io::{self, Write};
+use std::process::{Child, Command};
+
+/// Something that can read text aloud.
+pub trait Backend {
+    fn speak(&mut self, text: &str) -> io::Result<()>;
+}
+
+/// Speaks by running a program such as `espeak` or `say` with the text as
+/// its last argument. Each utterance cuts off the one before it.
+pub struct CommandBackend {
+    program: String,
+    args: Vec<String>,
+    child: Option<Child>,
+}
+
+impl CommandBackend {
+    /// `command` is the program followed by any arguments, separated by
+    /// spaces, e.g. `espeak -s 200`.
+    pub fn new(command: &str) -> CommandBackend {
+        let mut words = command.split_whitespace().map(|w| w.to_owned());
+        CommandBackend {
+            program: words.next().unwrap_or_default(),
+            args: words.collect(),
+            child: None,
+        }
+    }
+}
+
+impl Backend for CommandBackend {
+    fn speak(&mut self, text: &str) -> io::Result<()> {
+        if let Some(mut child) = self.child.take() {
+            // It may already have finished on its own.
+            let _ = child.kill();
+            child.wait()?;
+        }
+        self.child = Some(
+            Command::new(&self.program)
+                .args(&self.args)
+                .arg(text)
+                .spawn()?,
+        );
+        Ok(())
+    }
+}
+
+/// Writes each utterance as a line instead of speaking it, for testing and
+/// for systems without a speech synthesizer.
+pub struct LogBackend<W: Write> {
+    out: W,
+}
+
+impl<W: Write> LogBackend<W> {
+    pub fn new(out: W) -> LogBackend<W> {
+        LogBackend { out }
+    }
+}
+
+impl<W: Write> Backend for LogBackend<W> {
+    fn speak(&mut self, text: &str) -> io::Result<()> {
+        writeln!(self.out, "{}", text)?;
+        self.out.flush()
+    }
+}
+
+/// Speaks through whichever backend was chosen at startup, or one known
+/// to the caller.
+pub struct Speech<B: Backend + ?Sized = dyn Backend> {
+    backend: Box<B>,
+    pub enabled: bool,
+}
+
+impl Speech {
+    pub fn new(backend: Box<dyn Backend>, enabled: bool) -> Speech {
+        Speech { backend, enabled }
+    }
+}
+
+impl<B: Backend + ?Sized> Speech<B> {
+    pub fn say(&mut self, text: &str) {
+        if !self.enabled {
+            return;
+        }
+        if let Err(e) = self.backend.speak(text) {
+            println!("Speech failed, turning it off: {}", e);
+            self.enabled = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Broken;
+
+    impl Write for Broken {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("no synthesizer"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn logged(enabled: bool) -> Speech<LogBackend<Vec<u8>>> {
+        Speech {
+            backend: Box::new(LogBackend::new(Vec::new())),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn log_backend_writes_a_line_per_utterance() {
+        let mut backend = LogBackend::new(Vec::new());
+        backend.speak("Level 1").unwrap();
+        backend.speak("Paused").unwrap();
+        assert_eq!(backend.out, b"Level 1\nPaused\n".to_vec());
+    }
+
+    #[test]
+    fn says_nothing_while_disabled() {
+        let mut speech = logged(false);
+        speech.say("Hidden");
+        assert!(speech.backend.out.is_empty());
+
+        speech.enabled = true;
+        speech.say("Heard");
+        assert_eq!(speech.backend.out, b"Heard\n".to_vec());
+    }
+
+    #[test]
+    fn turns_off_after_backend_error() {
+        let mut speech = Speech::new(Box::new(LogBackend::new(Broken)), true);
+        speech.say("Level 1");
+        assert!(!speech.enabled);
+    }
+}
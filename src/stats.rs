@@ -0,0 +1,427 @@
+//! Ear-training progress. For each note, and each interval it was reached
+//! by, the game tallies how often the player found and shot the enemy
+//! sounding it, how long that took, and which other enemies they shot
+//! instead. The tallies are kept in the save, shown on the statistics
+//! screen and can be exported for a teacher as CSV or JSON.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json;
+
+use modes::GameMode;
+use training::interval_name;
+use waves::notes;
+
+/// Notes and intervals heard fewer times than this aren't ranked, since a
+/// miss or two would make them look weaker than they are.
+const MIN_HEARD: u32 = 3;
+/// Levels played that are kept, oldest dropped first.
+const MAX_SESSIONS: usize = 200;
+/// Levels at each end of the history compared to show improvement.
+const TREND_SESSIONS: usize = 5;
+
+/// How the player dealt with one enemy they were meant to shoot, or one
+/// note they were asked to name.
+#[derive(Debug, Clone)]
+pub struct Encounter {
+    pub freq: f32,
+    /// Semitones from the note before in a melody, or from the root in a
+    /// question.
+    pub interval: Option<i32>,
+    /// Seconds from appearing to the player first lining up a shot.
+    pub found_after: Option<f32>,
+    /// Seconds from appearing to being shot, if it was.
+    pub hit_after: Option<f32>,
+    /// Pitches shot or named instead.
+    pub wrong: Vec<f32>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Tally {
+    pub heard: u32,
+    pub found: u32,
+    pub hit: u32,
+    /// Totals, for the means.
+    pub find_seconds: f32,
+    pub hit_seconds: f32,
+    /// How many times each note was shot instead, by name.
+    pub wrong: BTreeMap<String, u32>,
+}
+
+impl Tally {
+    fn add(&mut self, encounter: &Encounter) {
+        self.heard += 1;
+        if let Some(seconds) = encounter.found_after {
+            self.found += 1;
+            self.find_seconds += seconds;
+        }
+        if let Some(seconds) = encounter.hit_after {
+            self.hit += 1;
+            self.hit_seconds += seconds;
+        }
+        for &freq in &encounter.wrong {
+            *self.wrong.entry(notes::name(freq)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn hit_rate(&self) -> f32 {
+        if self.heard == 0 {
+            0.0
+        } else {
+            self.hit as f32 / self.heard as f32
+        }
+    }
+
+    pub fn mean_find_time(&self) -> Option<f32> {
+        if self.found == 0 {
+            None
+        } else {
+            Some(self.find_seconds / self.found as f32)
+        }
+    }
+
+    pub fn mean_hit_time(&self) -> Option<f32> {
+        if self.hit == 0 {
+            None
+        } else {
+            Some(self.hit_seconds / self.hit as f32)
+        }
+    }
+
+    /// The note most often shot instead.
+    pub fn confused_with(&self) -> Option<&str> {
+        self.wrong
+            .iter()
+            .max_by_key(|&(_, &count)| count)
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// One level played through to the end or to game over.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Session {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub level: String,
+    pub mode: GameMode,
+    pub heard: u32,
+    pub hit: u32,
+}
+
+impl Session {
+    pub fn hit_rate(&self) -> f32 {
+        if self.heard == 0 {
+            0.0
+        } else {
+            self.hit as f32 / self.heard as f32
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    /// Keyed by note name, e.g. `C#4`.
+    pub notes: BTreeMap<String, Tally>,
+    /// Keyed by `describe_interval`.
+    pub intervals: BTreeMap<String, Tally>,
+    /// Oldest first.
+    pub sessions: Vec<Session>,
+}
+
+impl Stats {
+    /// Adds a level's encounters, and the level to the history.
+    pub fn record(&mut self, level: &str, mode: GameMode, encounters: &[Encounter]) {
+        if encounters.is_empty() {
+            return;
+        }
+        let mut session = Tally::default();
+        for encounter in encounters {
+            session.add(encounter);
+            self.notes
+                .entry(notes::name(encounter.freq))
+                .or_default()
+                .add(encounter);
+            if let Some(semitones) = encounter.interval {
+                self.intervals
+                    .entry(describe_interval(semitones))
+                    .or_default()
+                    .add(encounter);
+            }
+        }
+
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        self.sessions.push(Session {
+            time,
+            level: level.to_owned(),
+            mode,
+            heard: session.heard,
+            hit: session.hit,
+        });
+        if self.sessions.len() > MAX_SESSIONS {
+            let excess = self.sessions.len() - MAX_SESSIONS;
+            self.sessions.drain(..excess);
+        }
+    }
+
+    /// The `count` notes with the lowest hit rate.
+    pub fn weakest_notes(&self, count: usize) -> Vec<(&str, &Tally)> {
+        weakest(&self.notes, count)
+    }
+
+    pub fn weakest_intervals(&self, count: usize) -> Vec<(&str, &Tally)> {
+        weakest(&self.intervals, count)
+    }
+
+    /// How often the named note is missed, once it has been heard enough
+    /// to tell.
+    pub fn miss_rate(&self, name: &str) -> Option<f32> {
+        self.notes
+            .get(name)
+            .filter(|t| t.heard >= MIN_HEARD)
+            .map(|t| 1.0 - t.hit_rate())
+    }
+
+    /// How often notes in the given octave are missed, as for `miss_rate`.
+    pub fn register_miss_rate(&self, octave: i32) -> Option<f32> {
+        let (mut heard, mut hit) = (0, 0);
+        for (name, tally) in &self.notes {
+            if notes::parse_midi(name).map(|midi| midi / 12 - 1) == Some(octave) {
+                heard += tally.heard;
+                hit += tally.hit;
+            }
+        }
+        if heard < MIN_HEARD {
+            None
+        } else {
+            Some(1.0 - hit as f32 / heard as f32)
+        }
+    }
+
+    /// Every encounter so far, added up.
+    pub fn total(&self) -> Tally {
+        let mut total = Tally::default();
+        for tally in self.notes.values() {
+            total.heard += tally.heard;
+            total.found += tally.found;
+            total.hit += tally.hit;
+            total.find_seconds += tally.find_seconds;
+            total.hit_seconds += tally.hit_seconds;
+        }
+        total
+    }
+
+    /// The hit rate over the first few levels played and over the last
+    /// few, once there are enough to compare.
+    pub fn trend(&self) -> Option<(f32, f32)> {
+        let count = (self.sessions.len() / 2).min(TREND_SESSIONS);
+        if count == 0 {
+            return None;
+        }
+        let rate = |sessions: &[Session]| {
+            let heard: u32 = sessions.iter().map(|s| s.heard).sum();
+            let hit: u32 = sessions.iter().map(|s| s.hit).sum();
+            hit as f32 / heard.max(1) as f32
+        };
+        let last = self.sessions.len() - count;
+        Some((rate(&self.sessions[..count]), rate(&self.sessions[last..])))
+    }
+
+    /// One row per note and per interval.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(concat!(
+            "kind,name,heard,found,hit,hit_rate,",
+            "mean_find_seconds,mean_hit_seconds,confused_with\n"
+        ));
+        let rows = self
+            .notes
+            .iter()
+            .map(|t| ("note", t))
+            .chain(self.intervals.iter().map(|t| ("interval", t)));
+        for (kind, (name, tally)) in rows {
+            let seconds = |s: Option<f32>| s.map_or(String::new(), |s| format!("{:.2}", s));
+            csv.push_str(&format!(
+                "{},{},{},{},{},{:.2},{},{},{}\n",
+                quote(kind),
+                quote(name),
+                tally.heard,
+                tally.found,
+                tally.hit,
+                tally.hit_rate(),
+                seconds(tally.mean_find_time()),
+                seconds(tally.mean_hit_time()),
+                quote(tally.confused_with().unwrap_or(""))
+            ));
+        }
+        csv
+    }
+
+    /// One row per level played.
+    pub fn sessions_csv(&self) -> String {
+        let mut csv = String::from("time,level,mode,heard,hit,hit_rate\n");
+        for s in &self.sessions {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{:.2}\n",
+                s.time,
+                quote(&s.level),
+                quote(s.mode.name()),
+                s.heard,
+                s.hit,
+                s.hit_rate()
+            ));
+        }
+        csv
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self).map_err(|e| e.to_string())
+    }
+}
+
+/// A signed interval by name, e.g. `up minor 3rd`. Intervals wider than an
+/// octave are given in semitones.
+pub fn describe_interval(semitones: i32) -> String {
+    let direction = if semitones < 0 { "down" } else { "up" };
+    match semitones.abs() {
+        0 => "unison".to_owned(),
+        s if s <= 12 => format!("{} {}", direction, interval_name(s as u32)),
+        s => format!("{} {} semitones", direction, s),
+    }
+}
+
+/// A CSV text field, quoted so that commas, quotes and line breaks in it
+/// survive (RFC 4180).
+fn quote(field: &str) -> String {
+    format!("\"{}\"", field.replace('"', "\"\""))
+}
+
+/// Semitones from `from` up to `to`, to the nearest.
+pub fn semitones(from: f32, to: f32) -> i32 {
+    (12.0 * (to / from).log2()).round() as i32
+}
+
+fn weakest(tallies: &BTreeMap<String, Tally>, count: usize) -> Vec<(&str, &Tally)> {
+    let mut ranked: Vec<(&str, &Tally)> = tallies
+        .iter()
+        .filter(|&(_, t)| t.heard >= MIN_HEARD)
+        .map(|(name, t)| (name.as_str(), t))
+        .collect();
+    ranked.sort_by(|a, b| a.1.hit_rate().partial_cmp(&b.1.hit_rate()).unwrap());
+    ranked.truncate(count);
+    ranked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encounter(midi: i32, hit: bool, wrong: &[i32]) -> Encounter {
+        Encounter {
+            freq: notes::midi_to_freq(midi),
+            interval: Some(2),
+            found_after: Some(1.0),
+            hit_after: if hit { Some(2.0) } else { None },
+            wrong: wrong.iter().map(|&m| notes::midi_to_freq(m)).collect(),
+        }
+    }
+
+    fn session(heard: u32, hit: u32) -> Session {
+        Session {
+            time: 0,
+            level: "level".to_owned(),
+            mode: GameMode::Classic,
+            heard,
+            hit,
+        }
+    }
+
+    #[test]
+    fn intervals_by_name() {
+        assert_eq!(describe_interval(0), "unison");
+        assert_eq!(describe_interval(3), "up minor 3rd");
+        assert_eq!(describe_interval(-7), "down perfect 5th");
+        assert_eq!(describe_interval(12), "up octave");
+        assert_eq!(describe_interval(-14), "down 14 semitones");
+        assert_eq!(semitones(440.0, 880.0), 12);
+        assert_eq!(semitones(notes::midi_to_freq(64), notes::midi_to_freq(60)), -4);
+    }
+
+    #[test]
+    fn tallies_add_up() {
+        let mut stats = Stats::default();
+        let encounters = [
+            encounter(60, true, &[]),
+            encounter(60, false, &[62, 62, 64]),
+            encounter(60, true, &[]),
+            encounter(62, true, &[]),
+        ];
+        stats.record("level", GameMode::Classic, &encounters);
+
+        let c4 = &stats.notes["C4"];
+        assert_eq!((c4.heard, c4.found, c4.hit), (3, 3, 2));
+        assert_eq!(c4.mean_find_time(), Some(1.0));
+        assert_eq!(c4.mean_hit_time(), Some(2.0));
+        assert_eq!(c4.confused_with(), Some("D4"));
+        assert_eq!(stats.intervals["up major 2nd"].heard, 4);
+        assert_eq!(stats.total().hit, 3);
+        assert_eq!(stats.sessions.len(), 1);
+        assert_eq!((stats.sessions[0].heard, stats.sessions[0].hit), (4, 3));
+
+        // D4 hasn't been heard enough to judge.
+        assert_eq!(stats.miss_rate("D4"), None);
+        assert!((stats.miss_rate("C4").unwrap() - 1.0 / 3.0).abs() < 1e-6);
+        let weakest: Vec<&str> = stats.weakest_notes(5).iter().map(|&(n, _)| n).collect();
+        assert_eq!(weakest, vec!["C4"]);
+        assert_eq!(stats.register_miss_rate(4), Some(0.25));
+        assert_eq!(stats.register_miss_rate(5), None);
+    }
+
+    #[test]
+    fn nothing_heard_records_nothing() {
+        let mut stats = Stats::default();
+        stats.record("level", GameMode::Classic, &[]);
+        assert!(stats.sessions.is_empty());
+        assert_eq!(Tally::default().hit_rate(), 0.0);
+        assert_eq!(Tally::default().mean_find_time(), None);
+    }
+
+    #[test]
+    fn trend_compares_first_and_last_levels() {
+        let mut stats = Stats::default();
+        assert_eq!(stats.trend(), None);
+        stats.sessions.push(session(4, 1));
+        assert_eq!(stats.trend(), None);
+        stats.sessions.push(session(4, 3));
+        assert_eq!(stats.trend(), Some((0.25, 0.75)));
+
+        // Only the first and last few levels count.
+        stats.sessions = (0..20).map(|i| session(10, i / 2)).collect();
+        assert_eq!(stats.trend(), Some((0.08, 0.82)));
+    }
+
+    #[test]
+    fn csv_fields_are_quoted() {
+        let mut stats = Stats::default();
+        stats.sessions.push(Session {
+            level: "/levels/a, \"b\".toml".to_owned(),
+            ..session(2, 1)
+        });
+        assert_eq!(
+            stats.sessions_csv(),
+            concat!(
+                "time,level,mode,heard,hit,hit_rate\n",
+                "0,\"/levels/a, \"\"b\"\".toml\",\"Classic\",2,1,0.50\n"
+            )
+        );
+
+        stats.record("level", GameMode::Classic, &[encounter(60, false, &[62])]);
+        let csv = stats.to_csv();
+        assert!(csv.contains("\n\"note\",\"C4\",1,1,0,0.00,1.00,,\"D4\"\n"), "{}", csv);
+        assert!(csv.contains("\n\"interval\",\"up major 2nd\",1,1,0,"), "{}", csv);
+    }
+}
@@ -0,0 +1,200 @@
+//! Ear-training questions: pick out one tone of a chord, or name the
+//! interval between two tones.
+
+use rand::prelude::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChordQuality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+}
+
+impl ChordQuality {
+    pub const ALL: [ChordQuality; 4] = [
+        ChordQuality::Major,
+        ChordQuality::Minor,
+        ChordQuality::Diminished,
+        ChordQuality::Augmented,
+    ];
+
+    /// Semitones above the root of the third and fifth.
+    fn steps(&self) -> [i32; 3] {
+        match self {
+            ChordQuality::Major => [0, 4, 7],
+            ChordQuality::Minor => [0, 3, 7],
+            ChordQuality::Diminished => [0, 3, 6],
+            ChordQuality::Augmented => [0, 4, 8],
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChordQuality::Major => "major",
+            ChordQuality::Minor => "minor",
+            ChordQuality::Diminished => "diminished",
+            ChordQuality::Augmented => "augmented",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChordTone {
+    Root,
+    Third,
+    Fifth,
+}
+
+impl ChordTone {
+    const ALL: [ChordTone; 3] = [ChordTone::Root, ChordTone::Third, ChordTone::Fifth];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ChordTone::Root => "root",
+            ChordTone::Third => "third",
+            ChordTone::Fifth => "fifth",
+        }
+    }
+}
+
+const INTERVAL_NAMES: [&str; 12] = [
+    "minor 2nd",
+    "major 2nd",
+    "minor 3rd",
+    "major 3rd",
+    "perfect 4th",
+    "tritone",
+    "perfect 5th",
+    "minor 6th",
+    "major 6th",
+    "minor 7th",
+    "major 7th",
+    "octave",
+];
+
+/// Name of an interval of 1 to 12 semitones.
+pub fn interval_name(semitones: u32) -> &'static str {
+    INTERVAL_NAMES[(semitones as usize).clamp(1, 12) - 1]
+}
+
+fn transpose(freq: f32, semitones: i32) -> f32 {
+    freq * 2.0f32.powf(semitones as f32 / 12.0)
+}
+
+/// Which chords and intervals a level asks about.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingSettings {
+    pub chords: Vec<ChordQuality>,
+    /// Intervals in semitones, 1 (minor 2nd) to 12 (octave).
+    pub intervals: Vec<u32>,
+    /// Number of interval names offered as answers, at most 9.
+    pub choices: usize,
+    /// Notes per phrase in dictation.
+    pub phrase_length: usize,
+}
+
+impl Default for TrainingSettings {
+    fn default() -> TrainingSettings {
+        TrainingSettings {
+            chords: ChordQuality::ALL.to_vec(),
+            intervals: (1..13).collect(),
+            choices: 4,
+            phrase_length: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Question {
+    /// Shoot the `target` tone of the chord. `tones` are root, third and
+    /// fifth in that order.
+    Chord {
+        quality: ChordQuality,
+        tones: [f32; 3],
+        target: ChordTone,
+    },
+    /// Name the interval between two tones, choosing from `choices`.
+    Interval {
+        tones: [f32; 2],
+        semitones: u32,
+        choices: Vec<u32>,
+    },
+}
+
+impl Question {
+    pub fn chord(root: f32, settings: &TrainingSettings) -> Question {
+        let mut rng = thread_rng();
+        let quality = *rng.choose(&settings.chords).unwrap_or(&ChordQuality::Major);
+        let steps = quality.steps();
+        Question::Chord {
+            quality,
+            tones: [
+                transpose(root, steps[0]),
+                transpose(root, steps[1]),
+                transpose(root, steps[2]),
+            ],
+            target: *rng.choose(&ChordTone::ALL).unwrap(),
+        }
+    }
+
+    pub fn interval(root: f32, settings: &TrainingSettings) -> Question {
+        let mut rng = thread_rng();
+        let semitones = *rng.choose(&settings.intervals).unwrap_or(&7);
+
+        let mut others: Vec<u32> = (1..13).filter(|&s| s != semitones).collect();
+        rng.shuffle(&mut others);
+        let mut choices: Vec<u32> = others
+            .into_iter()
+            .take(settings.choices.saturating_sub(1))
+            .collect();
+        choices.push(semitones);
+        rng.shuffle(&mut choices);
+
+        Question::Interval {
+            tones: [root, transpose(root, semitones as i32)],
+            semitones,
+            choices,
+        }
+    }
+
+    /// Tones to sound, each paired with whether shooting it is correct.
+    pub fn tones(&self) -> Vec<(f32, bool)> {
+        match self {
+            Question::Chord { tones, target, .. } => ChordTone::ALL
+                .iter()
+                .zip(tones.iter())
+                .map(|(t, &f)| (f, t == target))
+                .collect(),
+            Question::Interval { tones, .. } => tones.iter().map(|&f| (f, false)).collect(),
+        }
+    }
+
+    pub fn prompt(&self) -> String {
+        match self {
+            Question::Chord { target, .. } => format!("Shoot the {} of the chord", target.name()),
+            Question::Interval { choices, .. } => {
+                let options: Vec<String> = choices
+                    .iter()
+                    .enumerate()
+                    .map(|(i, &s)| format!("{}) {}", i + 1, interval_name(s)))
+                    .collect();
+                format!("Which interval?  {}", options.join("  "))
+            }
+        }
+    }
+
+    /// What the question was, shown once it has been answered.
+    pub fn answer(&self) -> String {
+        match self {
+            Question::Chord {
+                quality, target, ..
+            } => format!("That was the {} of a {} chord", target.name(), quality.name()),
+            Question::Interval { semitones, .. } => {
+                format!("That was a {}", interval_name(*semitones))
+            }
+        }
+    }
+}
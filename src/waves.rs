@@ -4,7 +4,7 @@ use std::sync::mpsc::SyncSender;
 use std::sync::mpsc::{Receiver, Sender};
 
 use rodio::dynamic_mixer::mixer;
-use rodio::{default_output_device, Sink, Source};
+use rodio::{default_output_device, output_devices, Sink, Source};
 use std::f32::consts::PI;
 use std::sync::mpsc::sync_channel;
 use std::thread::spawn;
@@ -49,6 +49,66 @@ pub mod notes {
     pub const AS5: f32 = 932.33;
     pub const B5: f32 = 987.77;
     pub const C6: f32 = 1046.50;
+
+    const LETTERS: [(char, i32); 7] = [
+        ('C', 0),
+        ('D', 2),
+        ('E', 4),
+        ('F', 5),
+        ('G', 7),
+        ('A', 9),
+        ('B', 11),
+    ];
+    const NAMES: [&str; 12] = [
+        "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+    ];
+
+    /// Equal-tempered frequency of a MIDI note number (A4 = 69 = 440Hz).
+    pub fn midi_to_freq(midi: i32) -> f32 {
+        440.0 * 2.0f32.powf((midi - 69) as f32 / 12.0)
+    }
+
+    /// Fractional MIDI note number of a frequency.
+    pub fn freq_to_midi(freq: f32) -> f32 {
+        69.0 + 12.0 * (freq / 440.0).log2()
+    }
+
+    /// Parses a note name such as `C4`, `F#5`, `Bb3` or `CS4` into a MIDI
+    /// note number.
+    pub fn parse_midi(name: &str) -> Option<i32> {
+        let mut chars = name.trim().chars().peekable();
+        let letter = chars.next()?.to_ascii_uppercase();
+        let mut midi = LETTERS.iter().find(|&&(l, _)| l == letter)?.1;
+
+        while let Some(&c) = chars.peek() {
+            match c {
+                '#' | 's' | 'S' => midi += 1,
+                'b' => midi -= 1,
+                _ => break,
+            }
+            chars.next();
+        }
+
+        let octave: i32 = chars.collect::<String>().parse().ok()?;
+        Some(midi + (octave + 1) * 12)
+    }
+
+    /// Parses a note name into its frequency.
+    pub fn parse(name: &str) -> Option<f32> {
+        parse_midi(name).map(midi_to_freq)
+    }
+
+    /// Name of a MIDI note number, using sharps (`C#4`).
+    pub fn midi_name(midi: i32) -> String {
+        let pc = midi.rem_euclid(12);
+        let octave = (midi - pc) / 12 - 1;
+        format!("{}{}", NAMES[pc as usize], octave)
+    }
+
+    /// Name of the note nearest to a frequency.
+    pub fn name(freq: f32) -> String {
+        midi_name(freq_to_midi(freq).round() as i32)
+    }
 }
 
 pub fn sine_wave(fraction_through: f32) -> f32 {
@@ -63,7 +123,7 @@ pub fn square_wave(fraction_through: f32) -> f32 {
     }
 }
 
-fn saw_wave(fraction_through: f32) -> f32 {
+pub fn saw_wave(fraction_through: f32) -> f32 {
     fraction_through * 2.0 - 1.0
 }
 
@@ -115,10 +175,13 @@ pub enum WaveCommand {
     Update(u64, WaveUpdate),
     Replace(u64, DynamicWave),
     Delete(u64),
+    /// Master volume, from 0 to 1.
+    Volume(f32),
 }
 
 pub struct CompositeWave {
     waves: HashMap<u64, DynamicWave>,
+    volume: f32,
     sender: SyncSender<(f32, f32)>,
     command_reciever: Receiver<WaveCommand>,
 }
@@ -186,6 +249,7 @@ impl CompositeWave {
                 WaveCommand::Update(idx, up) => self.update(idx, up),
                 WaveCommand::Replace(idx, wave) => self.replace(idx, wave),
                 WaveCommand::Delete(idx) => self.delete(idx),
+                WaveCommand::Volume(volume) => self.volume = volume,
             }
         }
 
@@ -197,27 +261,39 @@ impl CompositeWave {
 impl Iterator for CompositeWave {
     type Item = (f32, f32);
     fn next(&mut self) -> Option<(f32, f32)> {
-        if self.waves.len() > 0 {
+        if !self.waves.is_empty() {
             let (l, r) = self
                 .waves
                 .values_mut()
                 .map(|w| w.next().unwrap())
                 .fold((0.0, 0.0), |m, n| (m.0 + n.0, m.1 + n.1));
-            Some((l / self.waves.len() as f32, r / self.waves.len() as f32))
+            let scale = self.volume / self.waves.len() as f32;
+            Some((l * scale, r * scale))
         } else {
             Some((0.0, 0.0))
         }
     }
 }
 
-pub fn make_waves() -> Sender<WaveCommand> {
-    let out = Sink::new(&default_output_device().unwrap());
+/// Names of the audio outputs that `make_waves` can play through.
+pub fn output_device_names() -> Vec<String> {
+    output_devices().map(|d| d.name()).collect()
+}
+
+/// Starts the synthesiser on the named output, or the default one if it
+/// isn't given or can't be found.
+pub fn make_waves(device: Option<&str>) -> Sender<WaveCommand> {
+    let device = device
+        .and_then(|name| output_devices().find(|d| d.name() == name))
+        .or_else(default_output_device);
+    let out = Sink::new(&device.unwrap());
     let (mix_in, mix_out) = mixer::<f32>(2, 48000);
 
     let (s, r) = sync_channel(1000);
     let (cs, cr) = channel();
     let mut cw = CompositeWave {
         waves: HashMap::new(),
+        volume: 1.0,
         sender: s,
         command_reciever: cr,
     };